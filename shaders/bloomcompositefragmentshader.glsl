@@ -0,0 +1,17 @@
+#version 300 es
+precision highp float;
+
+in vec2 TexCoords;
+
+uniform sampler2D sourceImage;
+// Largest bloom level, half the size of the frame
+uniform sampler2D bloomImage;
+uniform float intensity;
+
+out vec4 FragColor;
+
+void main() {
+    vec3 color = texture(sourceImage, TexCoords).rgb;
+    color += texture(bloomImage, TexCoords).rgb * intensity;
+    FragColor = vec4(color, 1.0);
+}
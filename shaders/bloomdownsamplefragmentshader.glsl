@@ -0,0 +1,50 @@
+#version 300 es
+precision highp float;
+
+in vec2 TexCoords;
+
+uniform sampler2D sourceImage;
+uniform vec2 texelSize;
+// Only the first halving keeps just what is brighter than the threshold
+uniform bool prefilter;
+uniform float threshold;
+
+out vec4 FragColor;
+
+vec3 sampleAt(vec2 offset) {
+    return texture(sourceImage, TexCoords + offset * texelSize).rgb;
+}
+
+// Soft knee, brightness fades in over the half of the threshold below it
+vec3 brightPart(vec3 color) {
+    float brightness = max(color.r, max(color.g, color.b));
+    float knee = 0.5 * threshold;
+    float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 1e-5);
+    float contribution = max(soft, brightness - threshold) / max(brightness, 1e-5);
+    return color * contribution;
+}
+
+// The 13 tap filter from Call of Duty: Advanced Warfare, five overlapping
+// boxes so the halving doesn't alias
+void main() {
+    vec3 a = sampleAt(vec2(-2.0, 2.0));
+    vec3 b = sampleAt(vec2(0.0, 2.0));
+    vec3 c = sampleAt(vec2(2.0, 2.0));
+    vec3 d = sampleAt(vec2(-2.0, 0.0));
+    vec3 e = sampleAt(vec2(0.0, 0.0));
+    vec3 f = sampleAt(vec2(2.0, 0.0));
+    vec3 g = sampleAt(vec2(-2.0, -2.0));
+    vec3 h = sampleAt(vec2(0.0, -2.0));
+    vec3 i = sampleAt(vec2(2.0, -2.0));
+    vec3 j = sampleAt(vec2(-1.0, 1.0));
+    vec3 k = sampleAt(vec2(1.0, 1.0));
+    vec3 l = sampleAt(vec2(-1.0, -1.0));
+    vec3 m = sampleAt(vec2(1.0, -1.0));
+
+    vec3 color = e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625 + (j + k + l + m) * 0.125;
+    if (prefilter) {
+        color = brightPart(color);
+    }
+    FragColor = vec4(color, 1.0);
+}
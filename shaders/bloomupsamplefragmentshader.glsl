@@ -0,0 +1,22 @@
+#version 300 es
+precision highp float;
+
+in vec2 TexCoords;
+
+// The smaller level, added onto the larger one by blending
+uniform sampler2D sourceImage;
+uniform vec2 texelSize;
+
+out vec4 FragColor;
+
+vec3 sampleAt(vec2 offset) {
+    return texture(sourceImage, TexCoords + offset * texelSize).rgb;
+}
+
+// 3x3 tent filter
+void main() {
+    vec3 color = sampleAt(vec2(0.0, 0.0)) * 4.0;
+    color += (sampleAt(vec2(-1.0, 0.0)) + sampleAt(vec2(1.0, 0.0)) + sampleAt(vec2(0.0, -1.0)) + sampleAt(vec2(0.0, 1.0))) * 2.0;
+    color += sampleAt(vec2(-1.0, -1.0)) + sampleAt(vec2(1.0, -1.0)) + sampleAt(vec2(-1.0, 1.0)) + sampleAt(vec2(1.0, 1.0));
+    FragColor = vec4(color / 16.0, 1.0);
+}
@@ -0,0 +1,57 @@
+#version 300 es
+precision highp float;
+
+in vec2 TexCoords;
+
+out vec2 FragColor;
+
+const float PI = 3.14159265359;
+const uint SAMPLE_COUNT = 1024u;
+
+float radicalInverse(uint bits) {
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return float(bits) * 2.3283064365386963e-10;
+}
+
+vec3 importanceSampleGgx(vec2 xi, float alpha) {
+    float phi = 2.0 * PI * xi.x;
+    float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+    return vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta);
+}
+
+// Smith with the k image based lighting uses
+float geometrySmith(float nDotV, float nDotL, float roughness) {
+    float k = roughness * roughness / 2.0;
+    return nDotV / (nDotV * (1.0 - k) + k) * nDotL / (nDotL * (1.0 - k) + k);
+}
+
+// Scale and bias to F0 of the specular reflection integrated over the
+// hemisphere, by n.v along x and roughness along y
+void main() {
+    float nDotV = max(TexCoords.x, 1e-3);
+    float roughness = TexCoords.y;
+    vec3 viewDir = vec3(sqrt(1.0 - nDotV * nDotV), 0.0, nDotV);
+
+    float scale = 0.0;
+    float bias = 0.0;
+    for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+        vec3 halfway = importanceSampleGgx(vec2(float(i) / float(SAMPLE_COUNT), radicalInverse(i)), roughness * roughness);
+        vec3 lightDir = normalize(2.0 * dot(viewDir, halfway) * halfway - viewDir);
+        float nDotL = max(lightDir.z, 0.0);
+        if (nDotL <= 0.0) {
+            continue;
+        }
+        float nDotH = max(halfway.z, 0.0);
+        float vDotH = max(dot(viewDir, halfway), 0.0);
+        float visibility = geometrySmith(nDotV, nDotL, roughness) * vDotH / (nDotH * nDotV);
+        float fresnel = pow(1.0 - vDotH, 5.0);
+        scale += (1.0 - fresnel) * visibility;
+        bias += fresnel * visibility;
+    }
+    FragColor = vec2(scale, bias) / float(SAMPLE_COUNT);
+}
@@ -0,0 +1,19 @@
+#version 300 es
+precision highp float;
+
+in vec2 TexCoords;
+
+uniform sampler2D sourceImage;
+// Offset of red and blue at the corners, in texture coordinates
+uniform float strength;
+
+out vec4 FragColor;
+
+// Red is pushed outwards and blue inwards, more the further from the center
+void main() {
+    vec2 offset = (TexCoords - 0.5) * 2.0 * strength;
+    float red = texture(sourceImage, TexCoords + offset).r;
+    float green = texture(sourceImage, TexCoords).g;
+    float blue = texture(sourceImage, TexCoords - offset).b;
+    FragColor = vec4(red, green, blue, 1.0);
+}
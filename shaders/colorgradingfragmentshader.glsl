@@ -0,0 +1,21 @@
+#version 300 es
+precision highp float;
+
+in vec2 TexCoords;
+
+uniform sampler2D sourceImage;
+// Maps display colors to graded ones, see load_lut() in post.rs
+uniform highp sampler3D lut;
+// Blends between the original and the graded colors
+uniform float strength;
+
+out vec4 FragColor;
+
+void main() {
+    vec3 color = clamp(texture(sourceImage, TexCoords).rgb, 0.0, 1.0);
+    // Through the centers of the first and last texels rather than the edges
+    float lutSize = float(textureSize(lut, 0).x);
+    vec3 coordinates = color * ((lutSize - 1.0) / lutSize) + 0.5 / lutSize;
+    vec3 graded = texture(lut, coordinates).rgb;
+    FragColor = vec4(mix(color, graded, strength), 1.0);
+}
@@ -0,0 +1,17 @@
+#version 300 es
+precision highp float;
+
+in vec3 LocalPos;
+
+uniform sampler2D equirectangularMap;
+
+out vec4 FragColor;
+
+const float PI = 3.14159265359;
+
+void main() {
+    vec3 direction = normalize(LocalPos);
+    // The first image row is the top of the sky and lands at v = 0
+    vec2 uv = vec2(atan(direction.z, direction.x) / (2.0 * PI) + 0.5, 0.5 - asin(clamp(direction.y, -1.0, 1.0)) / PI);
+    FragColor = vec4(texture(equirectangularMap, uv).rgb, 1.0);
+}
@@ -0,0 +1,54 @@
+#version 300 es
+precision highp float;
+
+in vec2 TexCoords;
+
+uniform sampler2D sourceImage;
+uniform vec2 texelSize;
+
+out vec4 FragColor;
+
+const vec3 LUMA = vec3(0.299, 0.587, 0.114);
+const float REDUCE_MIN = 1.0 / 128.0;
+const float REDUCE_MUL = 1.0 / 8.0;
+// Longest blur along an edge, in pixels
+const float SPAN_MAX = 8.0;
+
+// Timothy Lottes' FXAA in its small console form: finds the direction of
+// the edge from the luma of the corners and blurs along it
+void main() {
+    vec3 rgbNW = texture(sourceImage, TexCoords + vec2(-1.0, -1.0) * texelSize).rgb;
+    vec3 rgbNE = texture(sourceImage, TexCoords + vec2(1.0, -1.0) * texelSize).rgb;
+    vec3 rgbSW = texture(sourceImage, TexCoords + vec2(-1.0, 1.0) * texelSize).rgb;
+    vec3 rgbSE = texture(sourceImage, TexCoords + vec2(1.0, 1.0) * texelSize).rgb;
+    vec3 rgbM = texture(sourceImage, TexCoords).rgb;
+
+    float lumaNW = dot(rgbNW, LUMA);
+    float lumaNE = dot(rgbNE, LUMA);
+    float lumaSW = dot(rgbSW, LUMA);
+    float lumaSE = dot(rgbSE, LUMA);
+    float lumaM = dot(rgbM, LUMA);
+    float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+    float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+    vec2 direction = vec2(
+        -((lumaNW + lumaNE) - (lumaSW + lumaSE)),
+        (lumaNW + lumaSW) - (lumaNE + lumaSE)
+    );
+    float reduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    float inverseMin = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * inverseMin, -SPAN_MAX, SPAN_MAX) * texelSize;
+
+    vec3 rgbA = 0.5 * (
+        texture(sourceImage, TexCoords + direction * (1.0 / 3.0 - 0.5)).rgb +
+        texture(sourceImage, TexCoords + direction * (2.0 / 3.0 - 0.5)).rgb
+    );
+    vec3 rgbB = rgbA * 0.5 + 0.25 * (
+        texture(sourceImage, TexCoords - direction * 0.5).rgb +
+        texture(sourceImage, TexCoords + direction * 0.5).rgb
+    );
+    // The wider blur reached past the edge, keep the narrow one
+    float lumaB = dot(rgbB, LUMA);
+    vec3 color = (lumaB < lumaMin || lumaB > lumaMax) ? rgbA : rgbB;
+    FragColor = vec4(color, 1.0);
+}
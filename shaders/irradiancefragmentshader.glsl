@@ -0,0 +1,33 @@
+#version 300 es
+precision highp float;
+
+in vec3 LocalPos;
+
+uniform samplerCube environmentMap;
+
+out vec4 FragColor;
+
+const float PI = 3.14159265359;
+const float SAMPLE_DELTA = 0.025;
+
+// Cosine weighted average of the environment over the hemisphere around the
+// normal, which is all a Lambertian surface sees of it
+void main() {
+    vec3 normal = normalize(LocalPos);
+    vec3 up = abs(normal.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(0.0, 0.0, 1.0);
+    vec3 right = normalize(cross(up, normal));
+    up = cross(normal, right);
+
+    vec3 irradiance = vec3(0.0);
+    float samples = 0.0;
+    for (float phi = 0.0; phi < 2.0 * PI; phi += SAMPLE_DELTA) {
+        for (float theta = 0.0; theta < 0.5 * PI; theta += SAMPLE_DELTA) {
+            vec3 tangentSample = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            vec3 sampleDir = tangentSample.x * right + tangentSample.y * up + tangentSample.z * normal;
+            // Lower mips keep the bright spots from turning into noise
+            irradiance += textureLod(environmentMap, sampleDir, 2.0).rgb * cos(theta) * sin(theta);
+            samples++;
+        }
+    }
+    FragColor = vec4(PI * irradiance / samples, 1.0);
+}
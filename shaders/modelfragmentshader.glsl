@@ -4,16 +4,63 @@ precision highp float;
 in vec3 FragPos;   
 in vec3 Normal;    
 in vec2 TexCoords; 
+in float ViewDepth;
 
-uniform float time;
 uniform vec3 cameraPos;
-uniform vec3 cameraDir;
+uniform float ambientIntensity;
+
+// Have to match MAX_LIGHTS in lighting.rs and MAX_SHADOW_LAYERS and
+// CASCADES in shadows.rs
+#define MAX_LIGHTS 16
+#define MAX_SHADOW_LAYERS 8
+#define CASCADES 3
+#define DIRECTIONAL_LIGHT 0
+#define POINT_LIGHT 1
+#define SPOT_LIGHT 2
+
+const float PI = 3.14159265359;
+// Perceptual roughness below this turns point light highlights into aliased
+// single pixels
+const float MIN_ROUGHNESS = 0.045;
+
+struct Light {
+    vec4 positionType;    // xyz position, w type
+    vec4 directionRange;  // xyz direction the light shines in, w range
+    vec4 colorIntensity;  // rgb color, w intensity
+    vec4 cone;            // cosines of the inner and outer cone angles
+    vec4 shadow;          // first shadow map layer or -1, bias, normal bias, layer count
+};
+
+layout(std140) uniform Lights {
+    Light lights[MAX_LIGHTS];
+    mat4 shadowMatrices[MAX_SHADOW_LAYERS];
+    vec4 cascadeSplits;   // view depth where each cascade ends
+    int lightCount;
+};
 
 uniform sampler2D albedoMap;    
 uniform sampler2D aoMap;        
 uniform sampler2D metallicSmoothnessMap;  
 uniform sampler2D normalMap;    
-uniform samplerCube environmentMap;
+uniform sampler2D emissiveMap;      // scaled by materialEmissive
+uniform samplerCube environmentMap;   // prefiltered, one mip per roughness step
+uniform highp sampler2DArrayShadow shadowMaps;
+uniform samplerCube irradianceMap;
+uniform sampler2D brdfLut;
+uniform sampler2D ssaoMap;          // screen space occlusion, the size of the frame
+uniform bool useSsao;
+
+uniform bool physicallyBased;
+uniform bool useEnvironment;
+uniform float environmentIntensity;
+uniform float environmentMaxLod;
+uniform mat3 environmentRotation;   // world to environment map directions
+
+uniform vec3 materialDiffuse;
+uniform vec3 materialSpecular;
+uniform vec3 materialEmissive;
+uniform float materialShininess;
+uniform float materialOpacity;
 
 out vec4 FragColor;
 
@@ -26,7 +73,12 @@ vec3 getNormalFromMap() {
     vec2 tex_dy = dFdy(TexCoords);
     
     vec3 N = normalize(Normal);
-    vec3 T = normalize(pos_dx * tex_dy.t - pos_dy * tex_dx.t);
+    // Meshes without texture coordinates have no tangent frame
+    vec3 tangent = pos_dx * tex_dy.t - pos_dy * tex_dx.t;
+    if (dot(tangent, tangent) < 1e-12) {
+        return N;
+    }
+    vec3 T = normalize(tangent);
     vec3 B = -normalize(cross(N, T));
     
     mat3 TBN = mat3(T, B, N);
@@ -34,43 +86,202 @@ vec3 getNormalFromMap() {
     return normalize(TBN * tangentNormal);
 }
 
+// 1 where the light reaches the fragment, 0 in its shadow
+float shadowFactor(Light light, vec3 geometryNormal) {
+    int layer = int(light.shadow.x);
+    if (layer < 0) {
+        return 1.0;
+    }
+
+    if (int(light.positionType.w) == DIRECTIONAL_LIGHT) {
+        int cascade = 0;
+        while (cascade < CASCADES - 1 && ViewDepth > cascadeSplits[cascade]) {
+            cascade++;
+        }
+        if (ViewDepth > cascadeSplits[CASCADES - 1]) {
+            return 1.0;
+        }
+        layer += cascade;
+    }
+
+    vec3 position = FragPos + geometryNormal * light.shadow.z;
+    vec4 clip = shadowMatrices[layer] * vec4(position, 1.0);
+    vec3 coords = clip.xyz / clip.w * 0.5 + 0.5;
+    if (any(lessThan(coords, vec3(0.0))) || any(greaterThan(coords, vec3(1.0)))) {
+        return 1.0;
+    }
+
+    // 3x3 PCF on top of the 2x2 the hardware comparison filters
+    vec2 texel = 1.0 / vec2(textureSize(shadowMaps, 0).xy);
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            vec2 offset = vec2(float(x), float(y)) * texel;
+            lit += texture(shadowMaps, vec4(coords.xy + offset, float(layer), coords.z - light.shadow.y));
+        }
+    }
+    return lit / 9.0;
+}
+
+// Reflectance at normal incidence, 4% for dielectrics and the base color for
+// metals as in the glTF metallic/roughness model
+vec3 baseReflectance(vec3 albedo, float metallic) {
+    return mix(vec3(0.04), albedo, metallic);
+}
+
+// Schlick with roughness for light from every direction, rough surfaces don't
+// get as bright at grazing angles
+vec3 fresnelSchlickRoughness(float nDotV, vec3 f0, float roughness) {
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(1.0 - nDotV, 5.0);
+}
+
+float distributionGgx(float nDotH, float alpha) {
+    float a2 = alpha * alpha;
+    float denominator = nDotH * nDotH * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denominator * denominator);
+}
+
+// Height correlated Smith masking and shadowing, divided by 4 n.l n.v
+float visibilitySmith(float nDotV, float nDotL, float alpha) {
+    float a2 = alpha * alpha;
+    float ggxV = nDotL * sqrt(nDotV * nDotV * (1.0 - a2) + a2);
+    float ggxL = nDotV * sqrt(nDotL * nDotL * (1.0 - a2) + a2);
+    return 0.5 / max(ggxV + ggxL, 1e-6);
+}
+
+// GGX specular and Lambert diffuse, the diffuse part only gets the light the
+// specular part doesn't reflect. Light intensities keep the legacy units, in
+// which 1 lights a white surface facing the light fully white, hence the PI.
+vec3 cookTorrance(vec3 albedo, float metallic, float roughness, vec3 normal, vec3 viewDir, vec3 lightDir) {
+    float nDotL = max(dot(normal, lightDir), 0.0);
+    if (nDotL <= 0.0) {
+        return vec3(0.0);
+    }
+    vec3 halfway = normalize(lightDir + viewDir);
+    float nDotV = clamp(dot(normal, viewDir), 1e-4, 1.0);
+    float nDotH = max(dot(normal, halfway), 0.0);
+    float vDotH = clamp(dot(viewDir, halfway), 0.0, 1.0);
+    float alpha = max(roughness, MIN_ROUGHNESS) * max(roughness, MIN_ROUGHNESS);
+
+    vec3 f0 = baseReflectance(albedo, metallic);
+    vec3 fresnel = f0 + (1.0 - f0) * pow(1.0 - vDotH, 5.0);
+    vec3 specular = fresnel * distributionGgx(nDotH, alpha) * visibilitySmith(nDotV, nDotL, alpha);
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo / PI;
+    return (diffuse + specular) * nDotL * PI;
+}
+
+vec3 blinnPhong(vec3 albedo, float metallic, float smoothness, vec3 normal, vec3 viewDir, vec3 lightDir) {
+    float diff = max(dot(normal, lightDir), 0.0);
+    vec3 diffuse = diff * albedo;
+
+    vec3 halfwayDir = normalize(lightDir + viewDir);
+    float spec = pow(max(dot(normal, halfwayDir), 0.0), materialShininess * smoothness);
+    vec3 specular = spec * metallic * materialSpecular * 2.0;
+    return diffuse + specular;
+}
+
+// Analytic fit of the BRDF lookup table, for ambient light without maps
+vec3 approximateBrdf(vec3 f0, float roughness, float nDotV) {
+    const vec4 c0 = vec4(-1.0, -0.0275, -0.572, 0.022);
+    const vec4 c1 = vec4(1.0, 0.0425, 1.04, -0.04);
+    vec4 r = roughness * c0 + c1;
+    float a004 = min(r.x * r.x, exp2(-9.28 * nDotV)) * r.x + r.y;
+    vec2 scaleBias = vec2(-1.04, 1.04) * a004 + r.zw;
+    return f0 * scaleBias.x + scaleBias.y;
+}
+
+// The flat ambient term as an environment of the same brightness everywhere
+vec3 uniformAmbient(vec3 albedo, float metallic, float roughness, vec3 normal, vec3 viewDir) {
+    float nDotV = clamp(dot(normal, viewDir), 0.0, 1.0);
+    vec3 f0 = baseReflectance(albedo, metallic);
+    vec3 fresnel = fresnelSchlickRoughness(nDotV, f0, roughness);
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo;
+    return (diffuse + approximateBrdf(f0, roughness, nDotV)) * ambientIntensity;
+}
+
+// Diffuse and specular light from the environment maps, with the split sum
+// approximation for the specular part
+vec3 environmentLighting(vec3 albedo, float metallic, float roughness, vec3 normal, vec3 viewDir) {
+    float nDotV = clamp(dot(normal, viewDir), 0.0, 1.0);
+    vec3 f0 = baseReflectance(albedo, metallic);
+    vec3 fresnel = fresnelSchlickRoughness(nDotV, f0, roughness);
+
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo * texture(irradianceMap, environmentRotation * normal).rgb;
+
+    vec3 reflectionDir = environmentRotation * reflect(-viewDir, normal);
+    vec3 prefiltered = textureLod(environmentMap, reflectionDir, roughness * environmentMaxLod).rgb;
+    vec2 brdf = texture(brdfLut, vec2(nDotV, roughness)).rg;
+    vec3 specular = prefiltered * (fresnel * brdf.x + brdf.y);
+
+    return (diffuse + specular) * environmentIntensity;
+}
+
 void main() {
-    vec4 albedo = texture(albedoMap, TexCoords);
+    vec4 albedo = texture(albedoMap, TexCoords) * vec4(materialDiffuse, materialOpacity);
     float ao = texture(aoMap, TexCoords).r;
+    if (useSsao) {
+        ao *= texelFetch(ssaoMap, ivec2(gl_FragCoord.xy), 0).r;
+    }
     vec4 metallicSmoothness = texture(metallicSmoothnessMap, TexCoords);
     float metallic = metallicSmoothness.r;
     float smoothness = metallicSmoothness.a;
     float roughness = 1.0 - smoothness;
     vec3 normal = getNormalFromMap();
+    vec3 emissive = texture(emissiveMap, TexCoords).rgb * materialEmissive;
 
     vec3 viewDir = normalize(cameraPos - FragPos);
     vec3 reflectionDir = reflect(-viewDir, normal);
 
-    vec3 lightColor = vec3(2.0); 
-
-    float innerCutOff = cos(radians(30.0));
-    float outerCutOff = cos(radians(35.0));
-    
-    vec3 fragToLight = normalize(cameraPos - FragPos);
-    float theta = dot(fragToLight, normalize(cameraDir));
-    float epsilon = innerCutOff - outerCutOff;
-    float spotIntensity = clamp((theta - outerCutOff) / epsilon, 0.0, 1.0);
+    vec3 lighting = vec3(0.0);
+    for (int i = 0; i < lightCount; i++) {
+        Light light = lights[i];
+        int type = int(light.positionType.w);
+        vec3 radiance = light.colorIntensity.rgb * light.colorIntensity.w;
 
-    float diff = max(-dot(normal, fragToLight), 0.0);
-    vec3 diffuse = diff * lightColor * albedo.rgb * spotIntensity;
+        vec3 lightDir;
+        if (type == DIRECTIONAL_LIGHT) {
+            lightDir = -light.directionRange.xyz;
+        } else {
+            vec3 toLight = light.positionType.xyz - FragPos;
+            // Clamped so fragments at the light and lights with a range of 0
+            // don't divide 0 by 0
+            float distance = max(length(toLight), 1e-4);
+            lightDir = toLight / distance;
 
-    vec3 halfwayDir = normalize(fragToLight + normalize(cameraDir));
-    float spec = pow(max(dot(normal, halfwayDir), 0.0), 128.0 * smoothness);
-    vec3 specular = spec * metallic * lightColor * spotIntensity * 2.0;
+            // Falls off like the old headlamp at a range of 100, and smoothly
+            // reaches zero at the range
+            float range = max(light.directionRange.w, 1e-4);
+            float window = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+            radiance *= window * window / (1.0 + 4.5 / range * distance + 75.0 / (range * range) * distance * distance);
 
-    vec3 ambient = lightColor * albedo.rgb * ao * 0.05;
-    vec3 reflection = texture(environmentMap, reflectionDir).rgb;
+            if (type == SPOT_LIGHT) {
+                float theta = dot(lightDir, -light.directionRange.xyz);
+                float epsilon = max(light.cone.x - light.cone.y, 1e-4);
+                radiance *= clamp((theta - light.cone.y) / epsilon, 0.0, 1.0);
+            }
+        }
 
-    float distance = length(cameraPos - FragPos);
-    float attenuation = 1.0 / (1.0 + 0.045 * distance + 0.0075 * distance * distance);
+        vec3 reflected = physicallyBased
+            ? cookTorrance(albedo.rgb, metallic, roughness, normal, viewDir, lightDir)
+            : blinnPhong(albedo.rgb, metallic, smoothness, normal, viewDir, lightDir);
+        lighting += reflected * radiance * shadowFactor(light, normalize(Normal));
+    }
 
-    vec3 finalColor = ambient + (diffuse + specular) * attenuation;
-    finalColor = mix(finalColor, reflection, metallic * smoothness);
+    vec3 finalColor;
+    if (physicallyBased) {
+        vec3 ambient = useEnvironment
+            ? environmentLighting(albedo.rgb, metallic, roughness, normal, viewDir)
+            : uniformAmbient(albedo.rgb, metallic, roughness, normal, viewDir);
+        finalColor = ambient * ao + lighting + emissive;
+    } else {
+        vec3 ambient = albedo.rgb * ao * ambientIntensity;
+        finalColor = ambient + lighting + emissive;
+        // Without an environment map there is nothing to reflect
+        if (useEnvironment) {
+            vec3 reflection = textureLod(environmentMap, environmentRotation * reflectionDir, 0.0).rgb * environmentIntensity;
+            finalColor = mix(finalColor, reflection, metallic * smoothness);
+        }
+    }
 
     FragColor = vec4(finalColor, albedo.a);
 }
\ No newline at end of file
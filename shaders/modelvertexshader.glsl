@@ -11,11 +11,14 @@ uniform mat4 projection;
 out vec3 FragPos;
 out vec3 Normal;
 out vec2 TexCoords;
+// Distance in front of the camera, picks the shadow cascade
+out float ViewDepth;
 
 void main() {
     FragPos = vec3(model * vec4(aPos, 1.0));
     Normal = mat3(transpose(inverse(model))) * aNormal;
     TexCoords = aTexCoords;
+    ViewDepth = -(view * model * vec4(aPos, 1.0)).z;
     
     gl_Position = projection * view * model * vec4(aPos, 1.0);
 }
\ No newline at end of file
@@ -0,0 +1,16 @@
+#version 300 es
+precision highp float;
+
+in vec3 ViewNormal;
+
+out vec4 FragColor;
+
+// The geometry normal, normal maps add detail too fine for the occlusion
+void main() {
+    vec3 normal = normalize(ViewNormal);
+    // Thin meshes are seen from both sides
+    if (!gl_FrontFacing) {
+        normal = -normal;
+    }
+    FragColor = vec4(normal, 1.0);
+}
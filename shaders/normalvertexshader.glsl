@@ -0,0 +1,16 @@
+#version 300 es
+
+layout (location = 0) in vec3 aPos;
+layout (location = 1) in vec3 aNormal;
+
+uniform mat4 model;
+uniform mat4 view;
+uniform mat4 projection;
+
+out vec3 ViewNormal;
+
+void main() {
+    mat4 modelView = view * model;
+    ViewNormal = mat3(transpose(inverse(modelView))) * aNormal;
+    gl_Position = projection * modelView * vec4(aPos, 1.0);
+}
@@ -0,0 +1,74 @@
+#version 300 es
+precision highp float;
+
+in vec3 LocalPos;
+
+uniform samplerCube environmentMap;
+uniform float environmentSize;
+uniform float roughness;
+
+out vec4 FragColor;
+
+const float PI = 3.14159265359;
+const uint SAMPLE_COUNT = 512u;
+
+float radicalInverse(uint bits) {
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return float(bits) * 2.3283064365386963e-10;
+}
+
+vec2 hammersley(uint i, uint count) {
+    return vec2(float(i) / float(count), radicalInverse(i));
+}
+
+// Halfway vector around the normal distributed like the GGX lobe
+vec3 importanceSampleGgx(vec2 xi, vec3 normal, float alpha) {
+    float phi = 2.0 * PI * xi.x;
+    float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+    vec3 halfway = vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta);
+
+    vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, normal));
+    vec3 bitangent = cross(normal, tangent);
+    return normalize(tangent * halfway.x + bitangent * halfway.y + normal * halfway.z);
+}
+
+float distributionGgx(float nDotH, float alpha) {
+    float a2 = alpha * alpha;
+    float denominator = nDotH * nDotH * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denominator * denominator);
+}
+
+// Split sum approximation: the view direction is assumed to be the normal
+void main() {
+    vec3 normal = normalize(LocalPos);
+    float alpha = roughness * roughness;
+
+    vec3 color = vec3(0.0);
+    float totalWeight = 0.0;
+    for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+        vec3 halfway = importanceSampleGgx(hammersley(i, SAMPLE_COUNT), normal, alpha);
+        vec3 lightDir = normalize(2.0 * dot(normal, halfway) * halfway - normal);
+        float nDotL = dot(normal, lightDir);
+        if (nDotL <= 0.0) {
+            continue;
+        }
+
+        // Samples covering more than a texel read a blurrier mip so bright
+        // spots don't sparkle
+        float nDotH = max(dot(normal, halfway), 0.0);
+        float pdf = distributionGgx(nDotH, alpha) / 4.0 + 1e-4;
+        float sampleSolidAngle = 1.0 / (float(SAMPLE_COUNT) * pdf);
+        float texelSolidAngle = 4.0 * PI / (6.0 * environmentSize * environmentSize);
+        float mip = roughness == 0.0 ? 0.0 : 0.5 * log2(sampleSolidAngle / texelSolidAngle);
+
+        color += textureLod(environmentMap, lightDir, max(mip, 0.0)).rgb * nDotL;
+        totalWeight += nDotL;
+    }
+    FragColor = vec4(color / totalWeight, 1.0);
+}
@@ -0,0 +1,32 @@
+#version 300 es
+precision highp float;
+
+in vec3 Direction;
+
+// Have to match MAP_SKY and GRADIENT_SKY in skybox.rs
+#define MAP_SKY 0
+#define GRADIENT_SKY 1
+
+uniform int skyType;
+uniform samplerCube skyMap;
+uniform mat3 environmentRotation;
+uniform vec3 zenithColor;
+uniform vec3 horizonColor;
+uniform vec3 groundColor;
+uniform float exposure;
+
+out vec4 FragColor;
+
+void main() {
+    vec3 direction = normalize(Direction);
+    vec3 color;
+    if (skyType == MAP_SKY) {
+        color = textureLod(skyMap, environmentRotation * direction, 0.0).rgb;
+    } else {
+        float height = direction.y;
+        color = height >= 0.0
+            ? mix(horizonColor, zenithColor, sqrt(height))
+            : mix(horizonColor, groundColor, sqrt(-height));
+    }
+    FragColor = vec4(color * exposure, 1.0);
+}
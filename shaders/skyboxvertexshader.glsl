@@ -0,0 +1,15 @@
+#version 300 es
+
+layout (location = 0) in vec3 aPos;
+
+uniform mat4 view;
+uniform mat4 projection;
+
+out vec3 Direction;
+
+void main() {
+    Direction = aPos;
+    vec4 position = projection * view * vec4(aPos, 1.0);
+    // z = w puts the sky on the far plane
+    gl_Position = position.xyww;
+}
@@ -0,0 +1,39 @@
+#version 300 es
+precision highp float;
+
+in vec2 TexCoords;
+
+// Relative difference in view depth past which a neighbour belongs to
+// another surface and is left out
+const float DEPTH_TOLERANCE = 0.05;
+
+uniform sampler2D occlusionImage;
+// Sampler precision defaults to lowp, too coarse for reconstructing positions
+uniform highp sampler2D depthMap;
+uniform mat4 inverseProjection;
+uniform vec2 texelSize;
+
+out vec4 FragColor;
+
+float viewDepth(vec2 coordinates) {
+    float depth = texture(depthMap, coordinates).r * 2.0 - 1.0;
+    vec4 position = inverseProjection * vec4(0.0, 0.0, depth, 1.0);
+    return position.z / position.w;
+}
+
+// Averages the 4x4 noise tile away without bleeding across edges
+void main() {
+    float depth = viewDepth(TexCoords);
+    float sum = 0.0;
+    float weights = 0.0;
+    for (int x = -2; x < 2; x++) {
+        for (int y = -2; y < 2; y++) {
+            vec2 coordinates = TexCoords + vec2(float(x), float(y)) * texelSize;
+            float weight = step(abs(viewDepth(coordinates) - depth), DEPTH_TOLERANCE * abs(depth));
+            sum += texture(occlusionImage, coordinates).r * weight;
+            weights += weight;
+        }
+    }
+    // The pixel itself always counts, unless it is the sky
+    FragColor = vec4(weights > 0.0 ? sum / weights : 1.0, 0.0, 0.0, 1.0);
+}
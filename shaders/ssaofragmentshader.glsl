@@ -0,0 +1,71 @@
+#version 300 es
+precision highp float;
+
+in vec2 TexCoords;
+
+// Has to match KERNEL_SIZE in ssao.rs
+#define KERNEL_SIZE 16
+
+// Cosine between the normal and an occluder below which it doesn't count,
+// keeps slightly curved surfaces from occluding themselves
+const float ANGLE_BIAS = 0.1;
+
+// Sampler precision defaults to lowp, too coarse for reconstructing positions
+uniform highp sampler2D depthMap;
+uniform highp sampler2D normalMap;   // view space
+uniform sampler2D noiseMap;    // rotations around the normal, tiled over the screen
+uniform vec3 samples[KERNEL_SIZE];
+uniform mat4 projection;
+uniform mat4 inverseProjection;
+uniform vec2 noiseScale;       // screen size in noise tiles
+uniform float radius;
+uniform float intensity;
+
+out vec4 FragColor;
+
+vec3 viewPosition(vec2 coordinates) {
+    float depth = texture(depthMap, coordinates).r;
+    vec4 position = inverseProjection * vec4(vec3(coordinates, depth) * 2.0 - 1.0, 1.0);
+    return position.xyz / position.w;
+}
+
+void main() {
+    // Nothing was drawn here, the sky isn't occluded
+    if (texture(depthMap, TexCoords).r >= 1.0) {
+        FragColor = vec4(1.0);
+        return;
+    }
+
+    vec3 position = viewPosition(TexCoords);
+    vec3 normal = normalize(texture(normalMap, TexCoords).xyz);
+    vec3 rotation = vec3(texture(noiseMap, TexCoords * noiseScale).xy, 0.0);
+    vec3 tangent = rotation - normal * dot(rotation, normal);
+    if (dot(tangent, tangent) < 1e-6) {
+        tangent = cross(normal, vec3(0.0, 0.0, 1.0));
+    }
+    tangent = normalize(tangent);
+    mat3 tbn = mat3(tangent, cross(normal, tangent), normal);
+
+    // Each sample picks a pixel nearby, whose surface occludes this one the
+    // more it rises above the tangent plane. Comparing against the surface
+    // rather than the sample point keeps flat ground from occluding itself
+    // where the depth is only known per pixel.
+    float occlusion = 0.0;
+    for (int i = 0; i < KERNEL_SIZE; i++) {
+        vec3 samplePosition = position + tbn * samples[i] * radius;
+        vec4 clip = projection * vec4(samplePosition, 1.0);
+        vec2 coordinates = clip.xy / clip.w * 0.5 + 0.5;
+        vec3 toOccluder = viewPosition(coordinates) - position;
+        float distanceSquared = dot(toOccluder, toOccluder);
+        if (distanceSquared < 1e-8) {
+            continue;
+        }
+        float elevation = dot(normal, toOccluder) * inversesqrt(distanceSquared);
+        // Geometry past the radius, like a wall far behind, doesn't occlude
+        float falloff = max(1.0 - distanceSquared / (radius * radius), 0.0);
+        occlusion += max(elevation - ANGLE_BIAS, 0.0) / (1.0 - ANGLE_BIAS) * falloff;
+    }
+
+    float visibility = 1.0 - occlusion / float(KERNEL_SIZE);
+    FragColor = vec4(pow(visibility, intensity), 0.0, 0.0, 1.0);
+}
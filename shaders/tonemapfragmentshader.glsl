@@ -0,0 +1,80 @@
+#version 300 es
+precision highp float;
+
+in vec2 TexCoords;
+
+// Have to match the operator constants in tonemap.rs
+#define REINHARD 0
+#define ACES 1
+#define AGX 2
+
+uniform sampler2D hdrImage;
+uniform int tonemapOperator;
+uniform float exposure;
+
+out vec4 FragColor;
+
+vec3 reinhard(vec3 color) {
+    return color / (1.0 + color);
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms
+const mat3 ACES_INPUT = mat3(
+    0.59719, 0.07600, 0.02840,
+    0.35458, 0.90834, 0.13383,
+    0.04823, 0.01566, 0.83777
+);
+const mat3 ACES_OUTPUT = mat3(
+    1.60475, -0.10208, -0.00327,
+    -0.53108, 1.10813, -0.07276,
+    -0.07367, -0.00605, 1.07602
+);
+
+vec3 aces(vec3 color) {
+    color = ACES_INPUT * color;
+    vec3 a = color * (color + 0.0245786) - 0.000090537;
+    vec3 b = color * (0.983729 * color + 0.4329510) + 0.238081;
+    return ACES_OUTPUT * (a / b);
+}
+
+// Polynomial fit of Blender's AgX base contrast curve, by Benjamin Wrensch
+const mat3 AGX_INSET = mat3(
+    0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+    0.0784335999999992, 0.878468636469772, 0.0784336,
+    0.0792237451477643, 0.0791661274605434, 0.879142973793104
+);
+const mat3 AGX_OUTSET = mat3(
+    1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+    -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+    -0.0990297440797205, -0.0989611768448433, 1.15107367264116
+);
+const float AGX_MIN_EV = -12.47393;
+const float AGX_MAX_EV = 4.026069;
+
+vec3 agx(vec3 color) {
+    color = AGX_INSET * color;
+    color = clamp(log2(max(color, 1e-10)), AGX_MIN_EV, AGX_MAX_EV);
+    color = (color - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+    vec3 x2 = color * color;
+    vec3 x4 = x2 * x2;
+    color = 15.5 * x4 * x2 - 40.14 * x4 * color + 31.96 * x4 - 6.868 * x2 * color + 0.4298 * x2 + 0.1191 * color - 0.00232;
+    // The curve ends in a 2.2 gamma display encoding, back to linear
+    return pow(max(AGX_OUTSET * color, 0.0), vec3(2.2));
+}
+
+vec3 linearToSrgb(vec3 color) {
+    color = clamp(color, 0.0, 1.0);
+    return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, color));
+}
+
+void main() {
+    vec3 color = texture(hdrImage, TexCoords).rgb * exposure;
+    if (tonemapOperator == REINHARD) {
+        color = reinhard(color);
+    } else if (tonemapOperator == ACES) {
+        color = aces(color);
+    } else {
+        color = agx(color);
+    }
+    FragColor = vec4(linearToSrgb(color), 1.0);
+}
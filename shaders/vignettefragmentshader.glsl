@@ -0,0 +1,20 @@
+#version 300 es
+precision highp float;
+
+in vec2 TexCoords;
+
+uniform sampler2D sourceImage;
+uniform float intensity;
+// Distance from the center where the darkening is complete, 1 is a corner
+uniform float radius;
+// How far inside radius the darkening starts
+uniform float softness;
+
+out vec4 FragColor;
+
+void main() {
+    vec3 color = texture(sourceImage, TexCoords).rgb;
+    float distanceFromCenter = length(TexCoords - 0.5) * sqrt(2.0);
+    float darkening = smoothstep(radius - softness, radius, distanceFromCenter);
+    FragColor = vec4(color * (1.0 - intensity * darkening), 1.0);
+}
@@ -0,0 +1,295 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use serde::{Deserialize, Serialize};
+use winit::event::MouseButton;
+use winit::keyboard::{KeyCode, ModifiersState};
+use crate::error::RendererError;
+use crate::input::InputState;
+use crate::utils;
+
+// Everything a key or mouse button can be bound to. The first group fires
+// once per press, the second lasts as long as the binding is held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    SaveScene,
+    ToggleCamera,
+    ToggleShading,
+    CycleTonemap,
+    ExposureUp,
+    ExposureDown,
+    ToggleBloom,
+    ToggleFxaa,
+    ToggleChromaticAberration,
+    ToggleVignette,
+    ToggleColorGrading,
+    ToggleAmbientOcclusion,
+    Screenshot,
+    SupersampledScreenshot,
+    Exit,
+    FrameScene,
+    MoveSceneUp,
+    MoveSceneDown,
+    MoveSceneLeft,
+    MoveSceneRight,
+    OrbitUp,
+    OrbitDown,
+    OrbitLeft,
+    OrbitRight,
+    OrbitDrag,
+    PanDrag,
+    FlyForward,
+    FlyBack,
+    FlyLeft,
+    FlyRight,
+    FlyUp,
+    FlyDown,
+    FlyFast,
+}
+
+impl Action {
+    // Stepping actions keep going with key repeat, toggles would flicker
+    pub fn repeats(self) -> bool {
+        matches!(
+            self,
+            Action::MoveSceneUp | Action::MoveSceneDown | Action::MoveSceneLeft | Action::MoveSceneRight
+                | Action::OrbitUp | Action::OrbitDown | Action::OrbitLeft | Action::OrbitRight
+                | Action::ExposureUp | Action::ExposureDown
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    // Key at this position on the keyboard whatever the layout, e.g. KeyW is
+    // Z on AZERTY
+    Key(KeyCode),
+    // Key producing this character in the current layout, always lowercase
+    Character(String),
+    Mouse(MouseButton),
+}
+
+// Written as modifiers and a trigger joined by '+', e.g. "Ctrl+KeyS",
+// "Shift+F12", "MouseLeft" or "s" for a character
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Binding {
+    pub modifiers: ModifiersState,
+    pub trigger: Trigger,
+}
+
+const MODIFIER_NAMES: [(&str, ModifiersState); 4] = [
+    ("Ctrl", ModifiersState::CONTROL),
+    ("Shift", ModifiersState::SHIFT),
+    ("Alt", ModifiersState::ALT),
+    ("Super", ModifiersState::SUPER),
+];
+
+const MOUSE_BUTTON_NAMES: [(&str, MouseButton); 5] = [
+    ("MouseLeft", MouseButton::Left),
+    ("MouseRight", MouseButton::Right),
+    ("MouseMiddle", MouseButton::Middle),
+    ("MouseBack", MouseButton::Back),
+    ("MouseForward", MouseButton::Forward),
+];
+
+impl TryFrom<String> for Binding {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        let mut parts: Vec<&str> = value.split('+').collect();
+        // "+" and "Ctrl++" bind the plus character itself
+        if value.ends_with("++") || value == "+" {
+            parts.truncate(parts.len() - 2);
+            parts.push("+");
+        }
+        let (name, modifier_names) = parts.split_last().ok_or_else(|| format!("Empty binding {:?}", value))?;
+
+        let mut modifiers = ModifiersState::empty();
+        for modifier_name in modifier_names {
+            let (_, modifier) = MODIFIER_NAMES
+                .iter()
+                .find(|(known, _)| known.eq_ignore_ascii_case(modifier_name))
+                .ok_or_else(|| format!("Unknown modifier {:?} in binding {:?}", modifier_name, value))?;
+            modifiers |= *modifier;
+        }
+
+        let trigger = if let Some((_, button)) = MOUSE_BUTTON_NAMES.iter().find(|(known, _)| known == name) {
+            Trigger::Mouse(*button)
+        } else if name.chars().count() == 1 {
+            Trigger::Character(name.to_lowercase())
+        } else {
+            // KeyCode names are the ones winit uses, KeyA, Digit1, ArrowUp, F12...
+            let code = serde_json::from_value(serde_json::Value::String(name.to_string()))
+                .map_err(|_| format!("Unknown key {:?} in binding {:?}", name, value))?;
+            Trigger::Key(code)
+        };
+
+        Ok(Self { modifiers, trigger })
+    }
+}
+
+impl From<Binding> for String {
+    fn from(binding: Binding) -> Self {
+        binding.to_string()
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, modifier) in MODIFIER_NAMES {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{}+", name)?;
+            }
+        }
+        match &self.trigger {
+            Trigger::Key(code) => match serde_json::to_value(code) {
+                Ok(serde_json::Value::String(name)) => write!(f, "{}", name),
+                _ => write!(f, "{:?}", code),
+            },
+            Trigger::Character(character) => write!(f, "{}", character),
+            Trigger::Mouse(button) => match MOUSE_BUTTON_NAMES.iter().find(|(_, known)| known == button) {
+                Some((name, _)) => write!(f, "{}", name),
+                None => write!(f, "{:?}", button),
+            },
+        }
+    }
+}
+
+impl Binding {
+    fn held(&self, input: &InputState) -> bool {
+        let held = match &self.trigger {
+            Trigger::Key(code) => input.key_held(*code),
+            Trigger::Character(character) => input.character_held(character),
+            Trigger::Mouse(button) => input.button_held(*button),
+        };
+        held && input.modifiers().contains(self.modifiers)
+    }
+}
+
+// Action to key and mouse button mapping, stored as JSON. A bindings file
+// only needs to list the actions it changes, the others keep their default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Bindings(BTreeMap<Action, Vec<Binding>>);
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let bindings = [
+            (Action::SaveScene, &["Ctrl+KeyS"][..]),
+            (Action::ToggleCamera, &["Tab"]),
+            (Action::ToggleShading, &["KeyP"]),
+            (Action::CycleTonemap, &["KeyT"]),
+            (Action::ExposureUp, &["Equal", "NumpadAdd"]),
+            (Action::ExposureDown, &["Minus", "NumpadSubtract"]),
+            (Action::ToggleBloom, &["Digit1"]),
+            (Action::ToggleFxaa, &["Digit2"]),
+            (Action::ToggleChromaticAberration, &["Digit3"]),
+            (Action::ToggleVignette, &["Digit4"]),
+            (Action::ToggleColorGrading, &["Digit5"]),
+            (Action::ToggleAmbientOcclusion, &["KeyO"]),
+            (Action::Screenshot, &["F12"]),
+            (Action::SupersampledScreenshot, &["Shift+F12"]),
+            (Action::Exit, &["Escape"]),
+            (Action::FrameScene, &["KeyF"]),
+            (Action::MoveSceneUp, &["KeyW"]),
+            (Action::MoveSceneDown, &["KeyS"]),
+            (Action::MoveSceneLeft, &["KeyA"]),
+            (Action::MoveSceneRight, &["KeyD"]),
+            (Action::OrbitUp, &["ArrowUp"]),
+            (Action::OrbitDown, &["ArrowDown"]),
+            (Action::OrbitLeft, &["ArrowLeft"]),
+            (Action::OrbitRight, &["ArrowRight"]),
+            (Action::OrbitDrag, &["MouseLeft"]),
+            (Action::PanDrag, &["MouseMiddle"]),
+            (Action::FlyForward, &["KeyW"]),
+            (Action::FlyBack, &["KeyS"]),
+            (Action::FlyLeft, &["KeyA"]),
+            (Action::FlyRight, &["KeyD"]),
+            (Action::FlyUp, &["KeyE"]),
+            (Action::FlyDown, &["KeyQ"]),
+            (Action::FlyFast, &["ShiftLeft", "ShiftRight"]),
+        ];
+
+        Self(bindings
+            .into_iter()
+            .map(|(action, names)| {
+                let bindings = names
+                    .iter()
+                    .map(|name| Binding::try_from(name.to_string()).expect("default binding"))
+                    .collect();
+                (action, bindings)
+            })
+            .collect())
+    }
+}
+
+impl Bindings {
+    pub fn load(path: &str) -> Result<Self, RendererError> {
+        let path = utils::get_asset_path(path);
+        let json = fs::read_to_string(&path)
+            .map_err(|source| RendererError::Io { path: path.clone(), source })?;
+        let overrides: BTreeMap<Action, Vec<Binding>> = serde_json::from_str(&json)
+            .map_err(|source| RendererError::Bindings { path, source })?;
+
+        let mut bindings = Self::default();
+        bindings.0.extend(overrides);
+        Ok(bindings)
+    }
+
+    // Actions bound to one of the triggers just pressed. When bindings with
+    // different modifiers match, only the most specific ones fire, so Ctrl+S
+    // doesn't also trigger S.
+    pub fn pressed(&self, triggers: &[Trigger], modifiers: ModifiersState) -> Vec<Action> {
+        let matches: Vec<(Action, u32)> = self.0
+            .iter()
+            .flat_map(|(action, bindings)| bindings.iter().map(move |binding| (*action, binding)))
+            .filter(|(_, binding)| triggers.contains(&binding.trigger) && modifiers.contains(binding.modifiers))
+            .map(|(action, binding)| (action, binding.modifiers.bits().count_ones()))
+            .collect();
+
+        let most_specific = matches.iter().map(|&(_, count)| count).max().unwrap_or(0);
+        let mut actions: Vec<Action> = matches
+            .into_iter()
+            .filter(|&(_, count)| count == most_specific)
+            .map(|(action, _)| action)
+            .collect();
+        actions.dedup();
+        actions
+    }
+
+    pub fn held(&self, action: Action, input: &InputState) -> bool {
+        self.0
+            .get(&action)
+            .is_some_and(|bindings| bindings.iter().any(|binding| binding.held(input)))
+    }
+
+    // 1 or -1 while only one of the actions is held, 0 otherwise
+    pub fn axis(&self, positive: Action, negative: Action, input: &InputState) -> f32 {
+        self.held(positive, input) as i32 as f32 - self.held(negative, input) as i32 as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_file_matches_the_defaults() {
+        assert_eq!(Bindings::load("bindings/default.json").unwrap(), Bindings::default());
+    }
+
+    #[test]
+    fn modifiers_pick_the_most_specific_binding() {
+        let bindings = Bindings::default();
+        let s = [Trigger::Key(KeyCode::KeyS), Trigger::Character(String::from("s"))];
+        assert_eq!(bindings.pressed(&s, ModifiersState::CONTROL), [Action::SaveScene]);
+        assert_eq!(bindings.pressed(&s, ModifiersState::SHIFT), [Action::MoveSceneDown, Action::FlyBack]);
+
+        let binding = Binding::try_from(String::from("ctrl+shift+MouseMiddle")).unwrap();
+        assert_eq!(binding.to_string(), "Ctrl+Shift+MouseMiddle");
+        assert!(Binding::try_from(String::from("Hyper+KeyA")).is_err());
+        assert!(Binding::try_from(String::from("NotAKey")).is_err());
+    }
+}
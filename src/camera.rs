@@ -0,0 +1,233 @@
+use glam::Vec3;
+use crate::scene::Camera;
+
+// Matches the 0.005 radians per pixel the model drag used to rotate by
+const DEGREES_PER_PIXEL: f32 = 0.2865;
+// Distance multiplier per scroll line
+const ZOOM_STEP: f32 = 0.9;
+// Looking straight up or down makes the up vector degenerate
+const MAX_PITCH: f32 = 89.0;
+// Extra room around the bounding sphere when framing
+const FRAME_MARGIN: f32 = 1.1;
+
+// Orbits the scene camera around its target. Input moves a goal camera, the
+// camera handed to the renderer follows it with exponential smoothing.
+pub struct OrbitCamera {
+    pub min_distance: f32,
+    pub max_distance: f32,
+    // Time constant of the smoothing in seconds, 0 follows input immediately
+    pub smoothing: f32,
+    current: Camera,
+    goal: Camera,
+}
+
+impl OrbitCamera {
+    pub fn new(camera: Camera) -> Self {
+        let mut orbit = Self {
+            min_distance: 0.5,
+            max_distance: 50.0,
+            smoothing: 0.08,
+            current: camera,
+            goal: camera,
+        };
+        orbit.clamp_goal();
+        orbit.current = orbit.goal;
+        orbit
+    }
+
+    pub fn camera(&self) -> Camera {
+        self.current
+    }
+
+    pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+        self.goal.yaw += yaw;
+        self.goal.pitch += pitch;
+        self.clamp_goal();
+    }
+
+    // Drags the scene along with the cursor
+    pub fn drag(&mut self, dx: f32, dy: f32) {
+        self.orbit(-dx * DEGREES_PER_PIXEL, -dy * DEGREES_PER_PIXEL);
+    }
+
+    // Moves the target along the camera's right and up axes, in world units
+    pub fn pan(&mut self, right: f32, up: f32) {
+        let forward = -self.goal.direction();
+        let right_axis = forward.cross(Vec3::Y).normalize();
+        let up_axis = right_axis.cross(forward);
+        self.goal.target += right_axis * right + up_axis * up;
+    }
+
+    // Pans so that the point under the cursor stays under it, for a
+    // viewport that is viewport_height pixels high
+    pub fn drag_pan(&mut self, dx: f32, dy: f32, viewport_height: f32) {
+        let units_per_pixel = 2.0 * self.goal.distance * (self.goal.fov.to_radians() * 0.5).tan() / viewport_height;
+        self.pan(-dx * units_per_pixel, dy * units_per_pixel);
+    }
+
+    // Positive lines move closer
+    pub fn zoom(&mut self, lines: f32) {
+        self.goal.distance *= ZOOM_STEP.powf(lines);
+        self.clamp_goal();
+    }
+
+    // Keeps the view direction and fits the bounding sphere of the box
+    pub fn frame(&mut self, min: Vec3, max: Vec3) {
+        let radius = ((max - min).length() * 0.5).max(f32::EPSILON);
+        self.goal.target = (min + max) * 0.5;
+        self.goal.distance = radius * FRAME_MARGIN / (self.goal.fov.to_radians() * 0.5).sin();
+        self.clamp_goal();
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        let t = if self.smoothing > 0.0 {
+            1.0 - (-dt / self.smoothing).exp()
+        } else {
+            1.0
+        };
+        let lerp = |from: f32, to: f32| from + (to - from) * t;
+
+        self.current.target = self.current.target.lerp(self.goal.target, t);
+        self.current.distance = lerp(self.current.distance, self.goal.distance);
+        self.current.yaw = lerp(self.current.yaw, self.goal.yaw);
+        self.current.pitch = lerp(self.current.pitch, self.goal.pitch);
+        self.current.fov = self.goal.fov;
+    }
+
+    fn clamp_goal(&mut self) {
+        self.goal.distance = self.goal.distance.clamp(self.min_distance, self.max_distance);
+        self.goal.pitch = self.goal.pitch.clamp(-MAX_PITCH, MAX_PITCH);
+    }
+}
+
+// Fly speed in units per second and its multiplier while sprinting
+const FLY_SPEED: f32 = 3.0;
+const FLY_FAST_MULTIPLIER: f32 = 4.0;
+// Mouse look, in degrees per count of raw mouse motion
+const LOOK_SENSITIVITY: f32 = 0.1;
+
+// Movement requested for one frame, each axis in -1..1
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FlyInput {
+    pub forward: f32,
+    pub right: f32,
+    pub up: f32,
+    pub fast: bool,
+}
+
+// First person camera moving freely through the scene. The orbit distance of
+// the camera it was created from is kept, so switching back to orbiting
+// pivots around the point that far in front of it.
+pub struct FlyCamera {
+    pub speed: f32,
+    pub fast_multiplier: f32,
+    pub sensitivity: f32,
+    position: Vec3,
+    camera: Camera,
+}
+
+impl FlyCamera {
+    pub fn new(camera: Camera) -> Self {
+        Self {
+            speed: FLY_SPEED,
+            fast_multiplier: FLY_FAST_MULTIPLIER,
+            sensitivity: LOOK_SENSITIVITY,
+            position: camera.position(),
+            camera,
+        }
+    }
+
+    pub fn camera(&self) -> Camera {
+        Camera {
+            target: self.position - self.camera.direction() * self.camera.distance,
+            ..self.camera
+        }
+    }
+
+    pub fn look(&mut self, dx: f32, dy: f32) {
+        self.camera.yaw -= dx * self.sensitivity;
+        self.camera.pitch = (self.camera.pitch - dy * self.sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    // Moves along the view direction, its horizontal right axis and world up
+    pub fn update(&mut self, dt: f32, input: FlyInput) {
+        let forward = -self.camera.direction();
+        let right = forward.cross(Vec3::Y).normalize();
+        let speed = if input.fast { self.speed * self.fast_multiplier } else { self.speed };
+        let movement = forward * input.forward + right * input.right + Vec3::Y * input.up;
+        self.position += movement.clamp_length_max(1.0) * speed * dt;
+    }
+}
+
+pub enum CameraController {
+    Orbit(OrbitCamera),
+    Fly(FlyCamera),
+}
+
+impl CameraController {
+    pub fn camera(&self) -> Camera {
+        match self {
+            CameraController::Orbit(orbit) => orbit.camera(),
+            CameraController::Fly(fly) => fly.camera(),
+        }
+    }
+
+    // Switches between orbiting and flying from the current view
+    pub fn toggle(&mut self) {
+        *self = match self {
+            CameraController::Orbit(orbit) => CameraController::Fly(FlyCamera::new(orbit.camera())),
+            CameraController::Fly(fly) => CameraController::Orbit(OrbitCamera::new(fly.camera())),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zoom_stays_in_range() {
+        let mut orbit = OrbitCamera::new(Camera::default());
+        orbit.smoothing = 0.0;
+        orbit.zoom(1000.0);
+        orbit.update(0.0);
+        assert_eq!(orbit.camera().distance, orbit.min_distance);
+        orbit.zoom(-1000.0);
+        orbit.update(0.0);
+        assert_eq!(orbit.camera().distance, orbit.max_distance);
+    }
+
+    #[test]
+    fn framing_centers_the_box() {
+        let mut orbit = OrbitCamera::new(Camera::default());
+        orbit.frame(Vec3::new(1.0, 1.0, 1.0), Vec3::new(3.0, 5.0, 3.0));
+        // Smoothing converges on the goal
+        for _ in 0..100 {
+            orbit.update(0.1);
+        }
+        let camera = orbit.camera();
+        assert!(camera.target.abs_diff_eq(Vec3::new(2.0, 3.0, 2.0), 1e-4));
+        let radius = Vec3::new(1.0, 2.0, 1.0).length();
+        assert!(camera.distance * (camera.fov.to_radians() * 0.5).sin() > radius);
+    }
+
+    #[test]
+    fn flying_keeps_the_view_when_toggling() {
+        let start = Camera { yaw: 40.0, pitch: -20.0, ..Default::default() };
+        let mut controller = CameraController::Orbit(OrbitCamera::new(start));
+        controller.toggle();
+        assert!(controller.camera().position().abs_diff_eq(start.position(), 1e-4));
+
+        let CameraController::Fly(fly) = &mut controller else {
+            panic!("expected fly camera");
+        };
+        fly.update(0.5, FlyInput { forward: 1.0, ..Default::default() });
+        let moved = fly.camera();
+        let travelled = start.position() - moved.position();
+        assert!(travelled.abs_diff_eq(start.direction() * FLY_SPEED * 0.5, 1e-4));
+
+        controller.toggle();
+        assert!(controller.camera().position().abs_diff_eq(moved.position(), 1e-4));
+        assert_eq!(controller.camera().yaw, 40.0);
+    }
+}
@@ -0,0 +1,247 @@
+use std::path::PathBuf;
+use glam::Vec3;
+use crate::bindings::Bindings;
+use crate::error::RendererError;
+use crate::model::Transform;
+use crate::render_manager::RenderConfig;
+use crate::scene::Scene;
+use crate::scene_graph::Node;
+
+// Used by --headless when no --size is given
+pub const HEADLESS_SIZE: (u32, u32) = (1280, 720);
+
+// Scene files are saved here when none was loaded
+pub const DEFAULT_SCENE: &str = "scene.json";
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    // render.scene is only filled in by load_scene()
+    pub render: RenderConfig,
+    pub scene_path: Option<String>,
+    // bindings is only filled in by load_bindings()
+    pub bindings_path: Option<String>,
+    pub bindings: Bindings,
+    // Models given on the command line, added to the scene as root nodes
+    pub models: Vec<Node>,
+    // None opens the window maximized
+    pub window_size: Option<(u32, u32)>,
+    pub fullscreen: bool,
+    // Render a single frame to this file instead of opening a window
+    pub headless: Option<PathBuf>,
+}
+
+pub fn usage(program: &str) -> String {
+    format!(
+        "Usage: {program} [OPTIONS] [MODEL [MODEL OPTIONS]]...
+
+Models are .obj, .gltf or .glb files relative to the asset directory. They are
+added to the scene as root nodes. Without a scene or models, two copies of the
+guitar are shown side by side.
+
+Model options, applying to the model before them:
+    --position X,Y,Z          Translation (default 0,0,0)
+    --rotation X,Y,Z          Rotation in degrees, applied X then Y then Z (default 0,0,0)
+    --scale S | X,Y,Z         Uniform or per-axis scale (default 1)
+
+Options:
+    --scene PATH              Load a JSON scene file, Ctrl+S saves it back (default {scene})
+    --bindings PATH           Load key and mouse bindings from a JSON file (see bindings/default.json)
+    --vertex-shader PATH      Vertex shader (default {vertex})
+    --fragment-shader PATH    Fragment shader (default {fragment})
+    --size WIDTHxHEIGHT       Window size, or image size with --headless (default maximized, {headless_width}x{headless_height} headless)
+    --fullscreen              Borderless fullscreen on the current monitor
+    --vsync, --no-vsync       Wait for vertical blank when presenting (default on)
+    --msaa SAMPLES            Multisample the frame the scene is lit into with 2, 4, 8 or 16 samples (default off)
+    --ssaa FACTOR             Render screenshots and headless frames FACTOR times larger and scale them down, 1 to 8 (default 1)
+    --shading MODEL           pbr or legacy (Blinn-Phong) lighting, P switches at runtime (default pbr)
+    --headless OUTPUT         Render one frame offscreen and save it to OUTPUT (.png or .exr)
+    -h, --help                Print this message",
+        scene = DEFAULT_SCENE,
+        vertex = crate::render_manager::DEFAULT_VERTEX_SHADER,
+        fragment = crate::render_manager::DEFAULT_FRAGMENT_SHADER,
+        headless_width = HEADLESS_SIZE.0,
+        headless_height = HEADLESS_SIZE.1,
+    )
+}
+
+pub fn wants_help(args: &[String]) -> bool {
+    args.iter().any(|arg| arg == "-h" || arg == "--help")
+}
+
+// args excludes the program name
+pub fn parse(args: &[String]) -> Result<Options, String> {
+    let mut render = RenderConfig {
+        scene: Scene::default(),
+        ..Default::default()
+    };
+    let mut scene_path = None;
+    let mut bindings_path = None;
+    let mut models: Vec<Node> = Vec::new();
+    let mut window_size = None;
+    let mut fullscreen = false;
+    let mut headless = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+
+        match arg.as_str() {
+            "--position" | "--rotation" | "--scale" => {
+                let value = value()?;
+                let model = models
+                    .last_mut()
+                    .ok_or_else(|| format!("{} must follow a model path", arg))?;
+                match arg.as_str() {
+                    "--position" => model.transform.position = parse_vec3(arg, value)?,
+                    "--rotation" => model.transform.rotation = parse_vec3(arg, value)?,
+                    _ => model.transform.scale = parse_scale(value)?,
+                }
+            }
+            "--scene" => scene_path = Some(value()?.clone()),
+            "--bindings" => bindings_path = Some(value()?.clone()),
+            "--vertex-shader" => render.vertex_shader = value()?.clone(),
+            "--fragment-shader" => render.fragment_shader = value()?.clone(),
+            "--size" => window_size = Some(parse_size(value()?)?),
+            "--fullscreen" => fullscreen = true,
+            "--vsync" => render.vsync = true,
+            "--no-vsync" => render.vsync = false,
+            "--msaa" => {
+                let value = value()?;
+                let samples: u8 = value
+                    .parse()
+                    .ok()
+                    .filter(|samples: &u8| *samples <= 16 && (*samples == 0 || samples.is_power_of_two()))
+                    .ok_or_else(|| format!("--msaa expects 0, 1, 2, 4, 8 or 16 samples, got {:?}", value))?;
+                // 0 and 1 sample are both no multisampling
+                render.msaa_samples = (samples > 1).then_some(samples);
+            }
+            "--ssaa" => {
+                let value = value()?;
+                render.ssaa = value
+                    .parse()
+                    .ok()
+                    .filter(|factor| (1..=8).contains(factor))
+                    .ok_or_else(|| format!("--ssaa expects a factor from 1 to 8, got {:?}", value))?;
+            }
+            "--shading" => render.shading = value()?.parse()?,
+            "--headless" => headless = Some(PathBuf::from(value()?)),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+            _ => models.push(Node::with_model(arg, Transform::default())),
+        }
+    }
+
+    Ok(Options {
+        render,
+        scene_path,
+        bindings_path,
+        bindings: Bindings::default(),
+        models,
+        window_size,
+        fullscreen,
+        headless,
+    })
+}
+
+impl Options {
+    pub fn load_scene(&mut self) -> Result<(), RendererError> {
+        let mut scene = match &self.scene_path {
+            Some(path) => Scene::load(path)?,
+            None if self.models.is_empty() => Scene::sample(),
+            None => Scene::default(),
+        };
+        scene.nodes.extend(self.models.iter().cloned());
+        self.render.scene = scene;
+        Ok(())
+    }
+
+    pub fn load_bindings(&mut self) -> Result<(), RendererError> {
+        if let Some(path) = &self.bindings_path {
+            self.bindings = Bindings::load(path)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_path(&self) -> &str {
+        self.scene_path.as_deref().unwrap_or(DEFAULT_SCENE)
+    }
+}
+
+fn parse_vec3(flag: &str, value: &str) -> Result<Vec3, String> {
+    let components: Vec<f32> = value
+        .split(',')
+        .map(|component| component.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("{} expects X,Y,Z, got {:?}", flag, value))?;
+    match components[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!("{} expects X,Y,Z, got {:?}", flag, value)),
+    }
+}
+
+fn parse_scale(value: &str) -> Result<Vec3, String> {
+    match value.parse::<f32>() {
+        Ok(scale) => Ok(Vec3::splat(scale)),
+        Err(_) => parse_vec3("--scale", value),
+    }
+}
+
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    value
+        .split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|&(width, height)| width > 0 && height > 0)
+        .ok_or_else(|| format!("--size expects WIDTHxHEIGHT, got {:?}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn model_options_apply_to_the_model_before_them() {
+        let options = parse(&args("a.obj --position 1,2,3 --scale 2 b.glb --rotation 0,90,0 --scale 1,2,3")).unwrap();
+        let [a, b] = &options.models[..] else {
+            panic!("expected two models, got {:?}", options.models);
+        };
+        assert_eq!(a.model.as_deref(), Some("a.obj"));
+        assert_eq!(a.transform.position, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(a.transform.scale, Vec3::splat(2.0));
+        assert_eq!(b.model.as_deref(), Some("b.glb"));
+        assert_eq!(b.transform.rotation, Vec3::new(0.0, 90.0, 0.0));
+        assert_eq!(b.transform.scale, Vec3::new(1.0, 2.0, 3.0));
+
+        assert_eq!(parse(&args("--scale 2")).unwrap_err(), "--scale must follow a model path");
+        assert_eq!(parse(&args("a.obj --position 1,2")).unwrap_err(), "--position expects X,Y,Z, got \"1,2\"");
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let options = parse(&args("--scene s.json --size 640x480 --no-vsync --msaa 4 --ssaa 2 --shading legacy --headless out.png")).unwrap();
+        assert_eq!(options.scene_path.as_deref(), Some("s.json"));
+        assert_eq!(options.window_size, Some((640, 480)));
+        assert!(!options.render.vsync);
+        assert_eq!(options.render.msaa_samples, Some(4));
+        assert_eq!(options.render.ssaa, 2);
+        assert_eq!(options.render.shading, crate::render_manager::ShadingModel::Legacy);
+        assert_eq!(options.headless, Some(PathBuf::from("out.png")));
+
+        // 0 and 1 sample both mean no multisampling
+        assert_eq!(parse(&args("--msaa 1")).unwrap().render.msaa_samples, None);
+    }
+
+    #[test]
+    fn bad_values_are_rejected() {
+        assert_eq!(parse(&args("--size 640")).unwrap_err(), "--size expects WIDTHxHEIGHT, got \"640\"");
+        assert_eq!(parse(&args("--size 0x480")).unwrap_err(), "--size expects WIDTHxHEIGHT, got \"0x480\"");
+        assert_eq!(parse(&args("--msaa 3")).unwrap_err(), "--msaa expects 0, 1, 2, 4, 8 or 16 samples, got \"3\"");
+        assert_eq!(parse(&args("--msaa 32")).unwrap_err(), "--msaa expects 0, 1, 2, 4, 8 or 16 samples, got \"32\"");
+        assert_eq!(parse(&args("--ssaa 0")).unwrap_err(), "--ssaa expects a factor from 1 to 8, got \"0\"");
+        assert_eq!(parse(&args("--ssaa x")).unwrap_err(), "--ssaa expects a factor from 1 to 8, got \"x\"");
+        assert_eq!(parse(&args("--size")).unwrap_err(), "--size needs a value");
+        assert_eq!(parse(&args("--frobnicate")).unwrap_err(), "Unknown option --frobnicate");
+    }
+}
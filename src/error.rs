@@ -0,0 +1,194 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+}
+
+impl ShaderStage {
+    pub fn gl_type(self) -> u32 {
+        match self {
+            ShaderStage::Vertex => glow::VERTEX_SHADER,
+            ShaderStage::Fragment => glow::FRAGMENT_SHADER,
+        }
+    }
+}
+
+impl fmt::Display for ShaderStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShaderStage::Vertex => write!(f, "vertex"),
+            ShaderStage::Fragment => write!(f, "fragment"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RendererError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Image {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    Obj {
+        path: PathBuf,
+        source: tobj::LoadError,
+    },
+    Gltf {
+        path: PathBuf,
+        source: gltf::Error,
+    },
+    Scene {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    Bindings {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    // The file was read but its contents can't be used
+    Asset {
+        path: PathBuf,
+        message: String,
+    },
+    ShaderCompile {
+        path: PathBuf,
+        stage: ShaderStage,
+        log: String,
+        // Lines of the source the log refers to, already numbered
+        excerpt: String,
+    },
+    ProgramLink {
+        log: String,
+    },
+    // Creating a GL object or framebuffer failed
+    Gl(String),
+    // Display, config, context or surface setup failed
+    Context {
+        action: &'static str,
+        message: String,
+    },
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RendererError::Io { path, source } => write!(f, "Failed to read or write {}: {}", path.display(), source),
+            RendererError::Image { path, source } => write!(f, "Failed to load or save image {}: {}", path.display(), source),
+            RendererError::Obj { path, source } => write!(f, "Failed to load OBJ file {}: {}", path.display(), source),
+            RendererError::Gltf { path, source } => write!(f, "Failed to load glTF file {}: {}", path.display(), source),
+            RendererError::Scene { path, source } => write!(f, "Invalid scene file {}: {}", path.display(), source),
+            RendererError::Bindings { path, source } => write!(f, "Invalid bindings file {}: {}", path.display(), source),
+            RendererError::Asset { path, message } => write!(f, "Invalid asset {}: {}", path.display(), message),
+            RendererError::ShaderCompile { path, stage, log, excerpt } => {
+                write!(f, "Failed to compile {} shader {}:\n{}", stage, path.display(), log.trim_end())?;
+                if !excerpt.is_empty() {
+                    write!(f, "\n\n{}", excerpt)?;
+                }
+                Ok(())
+            }
+            RendererError::ProgramLink { log } => write!(f, "Failed to link program:\n{}", log.trim_end()),
+            RendererError::Gl(message) => write!(f, "{}", message),
+            RendererError::Context { action, message } => write!(f, "{}: {}", action, message),
+        }
+    }
+}
+
+impl std::error::Error for RendererError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RendererError::Io { source, .. } => Some(source),
+            RendererError::Image { source, .. } => Some(source),
+            RendererError::Obj { source, .. } => Some(source),
+            RendererError::Gltf { source, .. } => Some(source),
+            RendererError::Scene { source, .. } => Some(source),
+            RendererError::Bindings { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+const EXCERPT_CONTEXT: usize = 2;
+
+// Numbers the source lines mentioned in a GL info log, with a couple of lines
+// around each and the offending ones marked with '>'.
+pub fn source_excerpt(source: &str, log: &str) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let error_lines: BTreeSet<usize> = log
+        .lines()
+        .filter_map(log_line_number)
+        .filter(|&line| line >= 1 && line <= lines.len())
+        .collect();
+
+    let mut shown = BTreeSet::new();
+    for &line in &error_lines {
+        shown.extend(line.saturating_sub(EXCERPT_CONTEXT).max(1)..=(line + EXCERPT_CONTEXT).min(lines.len()));
+    }
+
+    let mut excerpt = Vec::new();
+    let mut previous = None;
+    for line in shown {
+        if previous.is_some_and(|previous| line != previous + 1) {
+            excerpt.push(String::from("      ..."));
+        }
+        let marker = if error_lines.contains(&line) { '>' } else { ' ' };
+        excerpt.push(format!("{} {:>4} | {}", marker, line, lines[line - 1]));
+        previous = Some(line);
+    }
+    excerpt.join("\n")
+}
+
+// Drivers disagree on the format: Mesa writes "0:12(5): error", NVIDIA
+// "0(12) : error" and ANGLE "ERROR: 0:12: ...". In all of them the line is
+// the number following the first "<source>:" or "<source>(".
+fn log_line_number(log_line: &str) -> Option<usize> {
+    let bytes = log_line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() || (i > 0 && bytes[i - 1].is_ascii_digit()) {
+            i += 1;
+            continue;
+        }
+        let source_end = i + bytes[i..].iter().take_while(|b| b.is_ascii_digit()).count();
+        if matches!(bytes.get(source_end), Some(b':') | Some(b'(')) {
+            let line: String = log_line[source_end + 1..]
+                .chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            if let Ok(line) = line.parse() {
+                return Some(line);
+            }
+        }
+        i = source_end;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_driver_log_formats() {
+        assert_eq!(log_line_number("0:12(5): error: `foo' undeclared"), Some(12));
+        assert_eq!(log_line_number("0(7) : error C1008: undefined variable \"foo\""), Some(7));
+        assert_eq!(log_line_number("ERROR: 0:3: 'foo' : undeclared identifier"), Some(3));
+        assert_eq!(log_line_number("error: linking failed"), None);
+    }
+
+    #[test]
+    fn excerpt_marks_error_lines() {
+        let source = "#version 300 es\nprecision highp float;\nout vec4 color;\nvoid main() {\n    color = foo;\n}\n";
+        let excerpt = source_excerpt(source, "0:5(13): error: `foo' undeclared\n");
+        assert_eq!(
+            excerpt,
+            "     3 | out vec4 color;\n     4 | void main() {\n>    5 |     color = foo;\n     6 | }"
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use std::sync::Arc;
+use glam::{Mat4, Vec3};
+use image::RgbaImage;
+use crate::error::RendererError;
+use crate::material::{Material, TextureSource};
+use crate::model::{MeshData, Submesh};
+use crate::utils;
+
+pub struct Primitive {
+    // Same interleaved layout as utils::load_mesh: 3 pos + 3 normal + 2 uv
+    pub vertices: Vec<f32>,
+    pub indices: Vec<u32>,
+    pub material: Material,
+    // Node transform, including all of the node's parents
+    pub transform: Mat4,
+}
+
+pub fn load_gltf(path: &str) -> Result<Vec<Primitive>, RendererError> {
+    let gltf_path = utils::get_asset_path(path);
+    let (document, buffers, images) = gltf::import(&gltf_path)
+        .map_err(|source| RendererError::Gltf { path: gltf_path.clone(), source })?;
+    let invalid = |message: &str| RendererError::Asset { path: gltf_path.clone(), message: message.to_owned() };
+
+    let images: Vec<Arc<RgbaImage>> = images
+        .into_iter()
+        .map(|image| to_rgba(image).map(Arc::new).ok_or_else(|| invalid("image data does not match its size")))
+        .collect::<Result<_, _>>()?;
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| invalid("no scene"))?;
+
+    for material in document.materials() {
+        warn_unsupported(&material);
+    }
+
+    let mut primitives = Vec::new();
+    for node in scene.nodes() {
+        collect_node(&node, Mat4::IDENTITY, &buffers, &images, &mut primitives);
+    }
+    Ok(primitives)
+}
+
+// Packs every primitive into one vertex and index buffer with the node
+// transforms baked in, one submesh per primitive, like utils::load_mesh.
+pub fn load_gltf_mesh(path: &str) -> Result<MeshData, RendererError> {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let mut submeshes = Vec::new();
+    let mut materials = Vec::new();
+
+    for primitive in load_gltf(path)? {
+        let normal_matrix = primitive.transform.inverse().transpose();
+        let base_vertex = (vertices.len() / 8) as u32;
+
+        for vertex in primitive.vertices.chunks_exact(8) {
+            let position = primitive.transform.transform_point3(Vec3::from_slice(&vertex[0..3]));
+            let normal = normal_matrix.transform_vector3(Vec3::from_slice(&vertex[3..6])).normalize_or_zero();
+            vertices.extend_from_slice(&position.to_array());
+            vertices.extend_from_slice(&normal.to_array());
+            vertices.extend_from_slice(&vertex[6..8]);
+        }
+
+        submeshes.push(Submesh {
+            index_offset: indices.len() as u32,
+            index_count: primitive.indices.len() as u32,
+            material: materials.len(),
+        });
+        indices.extend(primitive.indices.iter().map(|index| index + base_vertex));
+        materials.push(primitive.material);
+    }
+
+    Ok(MeshData { vertices, indices, submeshes, materials })
+}
+
+fn collect_node(
+    node: &gltf::Node,
+    parent_transform: Mat4,
+    buffers: &[gltf::buffer::Data],
+    images: &[Arc<RgbaImage>],
+    primitives: &mut Vec<Primitive>,
+) {
+    let transform = parent_transform * Mat4::from_cols_array_2d(&node.transform().matrix());
+
+    if let Some(mesh) = node.mesh() {
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                eprintln!("Skipping {:?} primitive in mesh {}", primitive.mode(), mesh.name().unwrap_or("<unnamed>"));
+                continue;
+            }
+
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let mut positions: Vec<[f32; 3]> = positions.collect();
+            let mut texcoords: Vec<[f32; 2]> = reader
+                .read_tex_coords(0)
+                .map(|texcoords| texcoords.into_f32().collect())
+                .unwrap_or_default();
+            let mut indices: Vec<u32> = reader
+                .read_indices()
+                .map(|indices| indices.into_u32().collect())
+                .unwrap_or_else(|| (0..positions.len() as u32).collect());
+            let normals: Vec<[f32; 3]> = match reader.read_normals() {
+                Some(normals) => normals.collect(),
+                None => {
+                    // The spec asks for flat shading, which needs a vertex per
+                    // triangle corner to carry the face normal
+                    positions = unindexed(&positions, &indices);
+                    if !texcoords.is_empty() {
+                        texcoords = unindexed(&texcoords, &indices);
+                    }
+                    indices = (0..positions.len() as u32).collect();
+                    face_normals(&positions)
+                }
+            };
+
+            let mut vertices = Vec::with_capacity(positions.len() * 8);
+            for (i, position) in positions.iter().enumerate() {
+                vertices.extend_from_slice(position);
+                vertices.extend_from_slice(normals.get(i).unwrap_or(&[0.0, 0.0, 0.0]));
+                vertices.extend_from_slice(texcoords.get(i).unwrap_or(&[0.0, 0.0]));
+            }
+
+            primitives.push(Primitive {
+                vertices,
+                indices,
+                material: load_material(&primitive.material(), images),
+                transform,
+            });
+        }
+    }
+
+    for child in node.children() {
+        collect_node(&child, transform, buffers, images, primitives);
+    }
+}
+
+fn unindexed<T: Copy + Default>(attribute: &[T], indices: &[u32]) -> Vec<T> {
+    indices.iter().map(|&i| attribute.get(i as usize).copied().unwrap_or_default()).collect()
+}
+
+// One normal per corner of each triangle in an unindexed list of positions
+fn face_normals(positions: &[[f32; 3]]) -> Vec<[f32; 3]> {
+    positions
+        .chunks_exact(3)
+        .flat_map(|triangle| {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from_array(triangle[i]));
+            [(b - a).cross(c - a).normalize_or_zero().to_array(); 3]
+        })
+        .collect()
+}
+
+fn load_material(material: &gltf::Material, images: &[Arc<RgbaImage>]) -> Material {
+    let texture = |texture: gltf::Texture| TextureSource::Image(images[texture.source().index()].clone());
+    let pbr = material.pbr_metallic_roughness();
+    let base_color = pbr.base_color_factor();
+
+    let metallic_roughness = pbr
+        .metallic_roughness_texture()
+        .map(|info| images[info.texture().source().index()].as_ref());
+
+    Material {
+        name: material.name().unwrap_or("default").to_owned(),
+        diffuse: [base_color[0], base_color[1], base_color[2]],
+        dissolve: base_color[3],
+        emissive: material.emissive_factor(),
+        diffuse_texture: pbr.base_color_texture().map(|info| texture(info.texture())),
+        ambient_texture: material.occlusion_texture().map(|info| texture(info.texture())),
+        specular_texture: Some(TextureSource::Image(Arc::new(metallic_smoothness(
+            pbr.metallic_factor(),
+            pbr.roughness_factor(),
+            metallic_roughness,
+        )))),
+        normal_texture: material.normal_texture().map(|info| texture(info.texture())),
+        emissive_texture: material.emissive_texture().map(|info| texture(info.texture())),
+        ..Default::default()
+    }
+}
+
+// Every primitive is drawn opaque with its first set of texture coordinates
+fn warn_unsupported(material: &gltf::Material) {
+    let name = material.name().unwrap_or("<unnamed>");
+    if material.alpha_mode() != gltf::material::AlphaMode::Opaque {
+        eprintln!("Warning: material {} uses alpha mode {:?}, drawing it opaque", name, material.alpha_mode());
+    }
+
+    let pbr = material.pbr_metallic_roughness();
+    let tex_coords = [
+        ("base color", pbr.base_color_texture().map(|info| info.tex_coord())),
+        ("metallic/roughness", pbr.metallic_roughness_texture().map(|info| info.tex_coord())),
+        ("normal", material.normal_texture().map(|info| info.tex_coord())),
+        ("occlusion", material.occlusion_texture().map(|info| info.tex_coord())),
+        ("emissive", material.emissive_texture().map(|info| info.tex_coord())),
+    ];
+    for (texture, set) in tex_coords {
+        if let Some(set @ 1..) = set {
+            eprintln!("Warning: material {} samples its {} texture with TEXCOORD_{}, using TEXCOORD_0", name, texture, set);
+        }
+    }
+}
+
+// The shader expects metallic in R and smoothness in A, glTF stores roughness
+// in G and metallic in B, each scaled by its factor.
+fn metallic_smoothness(metallic: f32, roughness: f32, texture: Option<&RgbaImage>) -> RgbaImage {
+    let convert = |metallic_sample: f32, roughness_sample: f32| {
+        let metallic = (metallic * metallic_sample * 255.0).round() as u8;
+        let smoothness = ((1.0 - roughness * roughness_sample) * 255.0).round() as u8;
+        image::Rgba([metallic, metallic, metallic, smoothness])
+    };
+
+    match texture {
+        Some(texture) => RgbaImage::from_fn(texture.width(), texture.height(), |x, y| {
+            let pixel = texture.get_pixel(x, y);
+            convert(pixel[2] as f32 / 255.0, pixel[1] as f32 / 255.0)
+        }),
+        None => RgbaImage::from_pixel(1, 1, convert(1.0, 1.0)),
+    }
+}
+
+fn to_rgba(data: gltf::image::Data) -> Option<RgbaImage> {
+    use gltf::image::Format;
+
+    let (channels, bytes_per_channel) = match data.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+
+    let channel = |bytes: &[u8]| match bytes.len() {
+        1 => bytes[0],
+        2 => (u16::from_le_bytes([bytes[0], bytes[1]]) >> 8) as u8,
+        _ => (f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).clamp(0.0, 1.0) * 255.0).round() as u8,
+    };
+
+    let pixels = data.pixels
+        .chunks_exact(channels * bytes_per_channel)
+        .flat_map(|pixel| {
+            let mut rgba = [0, 0, 0, 255];
+            for (i, value) in pixel.chunks_exact(bytes_per_channel).enumerate() {
+                rgba[i] = channel(value);
+            }
+            // Single channel images (e.g. occlusion) are greyscale
+            if channels == 1 {
+                rgba[1] = rgba[0];
+                rgba[2] = rgba[0];
+            }
+            rgba
+        })
+        .collect();
+
+    RgbaImage::from_raw(data.width, data.height, pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primitives_without_normals_are_flat_shaded() {
+        // An indexed quad facing +Z with positions and texture coordinates only
+        let mesh = load_gltf_mesh("tests/fixtures/quad.glb").unwrap();
+        assert_eq!(mesh.vertices.len() / 8, 6);
+        assert_eq!(mesh.indices, (0..6).collect::<Vec<u32>>());
+        for vertex in mesh.vertices.chunks_exact(8) {
+            assert_eq!(vertex[3..6], [0.0, 0.0, 1.0]);
+        }
+        // Texture coordinates follow their corners
+        assert_eq!(mesh.vertices[8 * 2 + 6..8 * 2 + 8], [1.0, 0.0]);
+    }
+
+    #[test]
+    fn materials_are_loaded() {
+        let mesh = load_gltf_mesh("tests/fixtures/quad.glb").unwrap();
+        assert_eq!(mesh.submeshes.len(), 1);
+        let material = &mesh.materials[0];
+        assert_eq!(material.name, "glow");
+        assert_eq!(material.diffuse, [0.5, 0.5, 0.5]);
+        assert_eq!(material.emissive, [1.0, 0.5, 0.25]);
+
+        let emissive = material.emissive_texture.as_ref().unwrap().load().unwrap();
+        assert_eq!(emissive.get_pixel(0, 0).0, [255, 128, 0, 255]);
+        // Metallic 1 and roughness 0.25 without a texture
+        let metallic_smoothness = material.specular_texture.as_ref().unwrap().load().unwrap();
+        assert_eq!(metallic_smoothness.get_pixel(0, 0).0, [255, 255, 255, 191]);
+    }
+}
@@ -0,0 +1,250 @@
+// Golden-image regression tests: the sample models in objs/ are rendered
+// headless with fixed camera parameters and compared against the reference
+// PNGs in tests/golden. Run `GOLDEN_UPDATE=1 cargo test -- --ignored` to
+// (re)generate the references after an intended change to the look.
+
+use std::path::PathBuf;
+use glam::Vec3;
+use image::{Rgba, RgbaImage};
+use crate::error::RendererError;
+use crate::model::Transform;
+use crate::render_manager::{RenderConfig, RenderManager};
+use crate::scene::{Camera, Scene};
+use crate::scene_graph::Node;
+use crate::utils;
+
+struct GoldenCase {
+    name: &'static str,
+    model: &'static str,
+    size: (u32, u32),
+    camera: Camera,
+}
+
+const fn camera(target: Vec3, distance: f32, yaw: f32, pitch: f32) -> Camera {
+    Camera { target, distance, yaw, pitch, fov: 45.0 }
+}
+
+// Each case renders a single model at the origin
+const CASES: &[GoldenCase] = &[
+    GoldenCase {
+        name: "guitar",
+        model: "objs/Guitar_01_OBJ/Guitar_01.obj",
+        size: (256, 256),
+        camera: camera(Vec3::new(0.0, 0.5, 0.0), 2.5, 0.0, 0.0),
+    },
+    GoldenCase {
+        name: "monkey",
+        model: "objs/monkey.obj",
+        size: (256, 256),
+        camera: camera(Vec3::ZERO, 5.0, 0.0, 0.0),
+    },
+    GoldenCase {
+        name: "cube",
+        model: "objs/model.obj",
+        size: (256, 256),
+        camera: camera(Vec3::ZERO, 7.5, -30.0, -30.0),
+    },
+];
+
+#[derive(Clone, Copy)]
+struct Tolerance {
+    // CIE76 colour difference under which two pixels count as equal.
+    // Around 2.3 is a just noticeable difference.
+    max_delta_e: f32,
+    // Share of pixels allowed to exceed max_delta_e, to absorb rasterizer
+    // differences along edges between drivers.
+    max_failing_fraction: f32,
+}
+
+const TOLERANCE: Tolerance = Tolerance {
+    max_delta_e: 3.0,
+    max_failing_fraction: 0.002,
+};
+
+struct Comparison {
+    failing_pixels: usize,
+    total_pixels: usize,
+    max_delta_e: f32,
+    diff: RgbaImage,
+}
+
+impl Comparison {
+    fn passes(&self, tolerance: Tolerance) -> bool {
+        self.failing_pixels as f32 <= self.total_pixels as f32 * tolerance.max_failing_fraction
+    }
+}
+
+fn srgb_to_lab(pixel: Rgba<u8>) -> [f32; 3] {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(pixel[0]), linear(pixel[1]), linear(pixel[2]));
+
+    // D65 white point
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn delta_e(a: Rgba<u8>, b: Rgba<u8>) -> f32 {
+    let colour = {
+        let (a, b) = (srgb_to_lab(a), srgb_to_lab(b));
+        ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+    };
+    // Alpha is compared on the same 0..100 scale as lightness
+    let alpha = (a[3] as f32 - b[3] as f32).abs() / 2.55;
+    colour.max(alpha)
+}
+
+fn compare(reference: &RgbaImage, actual: &RgbaImage, tolerance: Tolerance) -> Comparison {
+    assert_eq!(reference.dimensions(), actual.dimensions(), "image sizes differ");
+
+    let mut diff = RgbaImage::new(reference.width(), reference.height());
+    let mut failing_pixels = 0;
+    let mut max_delta_e: f32 = 0.0;
+
+    for (x, y, expected) in reference.enumerate_pixels() {
+        let got = *actual.get_pixel(x, y);
+        let delta = delta_e(*expected, got);
+        max_delta_e = max_delta_e.max(delta);
+
+        // Failing pixels in red over a dimmed greyscale copy of the reference
+        let pixel = if delta > tolerance.max_delta_e {
+            failing_pixels += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let grey = ((expected[0] as u32 + expected[1] as u32 + expected[2] as u32) / 9) as u8;
+            Rgba([grey, grey, grey, 255])
+        };
+        diff.put_pixel(x, y, pixel);
+    }
+
+    Comparison {
+        failing_pixels,
+        total_pixels: (reference.width() * reference.height()) as usize,
+        max_delta_e,
+        diff,
+    }
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    utils::get_asset_path("tests/golden").join(format!("{}.png", name))
+}
+
+fn output_dir() -> PathBuf {
+    utils::get_asset_path("target/golden")
+}
+
+fn render_case(case: &GoldenCase) -> Result<RgbaImage, RendererError> {
+    let config = RenderConfig {
+        scene: Scene {
+            nodes: vec![Node::with_model(case.model, Transform::default())],
+            camera: case.camera,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut render_manager = RenderManager::new_headless(case.size.0, case.size.1, &config)?;
+    render_manager.render_offscreen()
+}
+
+fn check_case(case: &GoldenCase, update: bool) -> Result<(), String> {
+    let actual = render_case(case).map_err(|e| format!("{}: {}", case.name, e))?;
+    let reference_path = reference_path(case.name);
+
+    if update || !reference_path.exists() {
+        std::fs::create_dir_all(reference_path.parent().unwrap()).unwrap();
+        actual.save(&reference_path).unwrap();
+        return if update {
+            Ok(())
+        } else {
+            Err(format!("{}: no reference image, wrote {}", case.name, reference_path.display()))
+        };
+    }
+
+    let reference = image::open(&reference_path).unwrap().into_rgba8();
+    if reference.dimensions() != actual.dimensions() {
+        return Err(format!("{}: reference is {:?}, render is {:?}", case.name, reference.dimensions(), actual.dimensions()));
+    }
+
+    let comparison = compare(&reference, &actual, TOLERANCE);
+    if comparison.passes(TOLERANCE) {
+        return Ok(());
+    }
+
+    let output_dir = output_dir();
+    std::fs::create_dir_all(&output_dir).unwrap();
+    let actual_path = output_dir.join(format!("{}.actual.png", case.name));
+    let diff_path = output_dir.join(format!("{}.diff.png", case.name));
+    actual.save(&actual_path).unwrap();
+    comparison.diff.save(&diff_path).unwrap();
+
+    Err(format!(
+        "{}: {} of {} pixels differ (max delta E {:.1}), see {} and {}",
+        case.name,
+        comparison.failing_pixels,
+        comparison.total_pixels,
+        comparison.max_delta_e,
+        actual_path.display(),
+        diff_path.display(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(colour: [u8; 4]) -> RgbaImage {
+        RgbaImage::from_pixel(8, 8, Rgba(colour))
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let image = filled([120, 80, 40, 255]);
+        let comparison = compare(&image, &image, TOLERANCE);
+        assert_eq!(comparison.failing_pixels, 0);
+        assert_eq!(comparison.max_delta_e, 0.0);
+    }
+
+    #[test]
+    fn small_colour_shift_is_tolerated() {
+        let comparison = compare(&filled([120, 80, 40, 255]), &filled([121, 80, 41, 255]), TOLERANCE);
+        assert!(comparison.passes(TOLERANCE));
+    }
+
+    #[test]
+    fn visible_change_is_reported_in_diff() {
+        let reference = filled([120, 80, 40, 255]);
+        let mut actual = reference.clone();
+        actual.put_pixel(3, 4, Rgba([40, 80, 120, 255]));
+
+        let comparison = compare(&reference, &actual, TOLERANCE);
+        assert_eq!(comparison.failing_pixels, 1);
+        assert_eq!(*comparison.diff.get_pixel(3, 4), Rgba([255, 0, 0, 255]));
+        assert!(!comparison.passes(Tolerance { max_failing_fraction: 0.0, ..TOLERANCE }));
+    }
+
+    #[test]
+    fn alpha_change_is_reported() {
+        let comparison = compare(&filled([0, 0, 0, 255]), &filled([0, 0, 0, 0]), TOLERANCE);
+        assert_eq!(comparison.failing_pixels, 64);
+    }
+
+    #[test]
+    #[ignore = "needs an EGL device, run with `cargo test -- --ignored`"]
+    fn golden_images() {
+        let update = std::env::var_os("GOLDEN_UPDATE").is_some();
+        // One test for all cases: headless contexts share the EGL display,
+        // so they must not be created from parallel test threads.
+        let failures: Vec<String> = CASES
+            .iter()
+            .filter_map(|case| check_case(case, update).err())
+            .collect();
+        assert!(failures.is_empty(), "golden image mismatches:\n{}", failures.join("\n"));
+    }
+}
@@ -0,0 +1,322 @@
+use glam::{Mat4, Vec3};
+use glow::HasContext;
+use crate::error::RendererError;
+use crate::utils;
+
+const CUBEMAP_VERTEX_SHADER: &str = "shaders/cubemapvertexshader.glsl";
+const EQUIRECT_FRAGMENT_SHADER: &str = "shaders/equirecttocubefragmentshader.glsl";
+const IRRADIANCE_FRAGMENT_SHADER: &str = "shaders/irradiancefragmentshader.glsl";
+const PREFILTER_FRAGMENT_SHADER: &str = "shaders/prefilterfragmentshader.glsl";
+pub const FULLSCREEN_VERTEX_SHADER: &str = "shaders/fullscreenvertexshader.glsl";
+const BRDF_FRAGMENT_SHADER: &str = "shaders/brdffragmentshader.glsl";
+
+const ENVIRONMENT_SIZE: i32 = 512;
+const IRRADIANCE_SIZE: i32 = 32;
+const PREFILTER_SIZE: i32 = 128;
+// Mip 0 is a mirror, the last one fully rough
+pub const PREFILTER_MIPS: i32 = 5;
+const BRDF_LUT_SIZE: i32 = 256;
+
+// Maps precomputed from an equirectangular HDR environment for image based
+// lighting: diffuse irradiance, specular radiance prefiltered per roughness
+// and the split sum BRDF lookup table. The environment itself is kept for
+// the skybox.
+pub struct Ibl {
+    pub environment: glow::Texture,
+    pub irradiance: glow::Texture,
+    pub prefiltered: glow::Texture,
+    pub brdf_lut: glow::Texture,
+}
+
+impl Ibl {
+    pub fn load(gl: &glow::Context, path: &str) -> Result<Self, RendererError> {
+        let asset_path = utils::get_asset_path(path);
+        let image = image::open(&asset_path)
+            .map_err(|source| RendererError::Image { path: asset_path, source })?
+            .into_rgb32f();
+
+        unsafe {
+            let previous_framebuffer = gl.get_parameter_framebuffer(glow::DRAW_FRAMEBUFFER_BINDING);
+            let mut viewport = [0; 4];
+            gl.get_parameter_i32_slice(glow::VIEWPORT, &mut viewport);
+
+            let result = Self::generate(gl, &image);
+
+            gl.bind_framebuffer(glow::FRAMEBUFFER, previous_framebuffer);
+            gl.viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+            result
+        }
+    }
+
+    unsafe fn generate(gl: &glow::Context, image: &image::Rgb32FImage) -> Result<Self, RendererError> {
+        let equirect = gl.create_texture().map_err(RendererError::Gl)?;
+        gl.bind_texture(glow::TEXTURE_2D, Some(equirect));
+        gl.tex_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            glow::RGB16F as i32,
+            image.width() as i32,
+            image.height() as i32,
+            0,
+            glow::RGB,
+            glow::FLOAT,
+            glow::PixelUnpackData::Slice(Some(bytemuck::cast_slice(image.as_raw()))),
+        );
+        set_filtering(gl, glow::TEXTURE_2D, glow::LINEAR);
+
+        // Everything created from here on is deleted again, the maps only
+        // when a step fails
+        let mut framebuffer = None;
+        let mut cube = None;
+        let mut maps = Vec::new();
+        let result = (|| {
+            let framebuffer = *framebuffer.insert(gl.create_framebuffer().map_err(RendererError::Gl)?);
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer));
+            let cube = cube.insert(Cube::new(gl)?).vao;
+
+            let environment = create_cubemap(gl, ENVIRONMENT_SIZE, ENVIRONMENT_SIZE.ilog2() as i32 + 1)?;
+            maps.push(environment);
+            let program = utils::load_program(gl, CUBEMAP_VERTEX_SHADER, EQUIRECT_FRAGMENT_SHADER)?;
+            bind_sampler(gl, program, "equirectangularMap", glow::TEXTURE_2D, equirect);
+            let rendered = render_cube_faces(gl, program, cube, environment, ENVIRONMENT_SIZE, 0, |_| ());
+            gl.delete_program(program);
+            rendered?;
+            // Prefiltering samples lower mips for the rough directions
+            gl.bind_texture(glow::TEXTURE_CUBE_MAP, Some(environment));
+            gl.generate_mipmap(glow::TEXTURE_CUBE_MAP);
+
+            let irradiance = create_cubemap(gl, IRRADIANCE_SIZE, 1)?;
+            maps.push(irradiance);
+            let program = utils::load_program(gl, CUBEMAP_VERTEX_SHADER, IRRADIANCE_FRAGMENT_SHADER)?;
+            bind_sampler(gl, program, "environmentMap", glow::TEXTURE_CUBE_MAP, environment);
+            let rendered = render_cube_faces(gl, program, cube, irradiance, IRRADIANCE_SIZE, 0, |_| ());
+            gl.delete_program(program);
+            rendered?;
+
+            let prefiltered = create_cubemap(gl, PREFILTER_SIZE, PREFILTER_MIPS)?;
+            maps.push(prefiltered);
+            let program = utils::load_program(gl, CUBEMAP_VERTEX_SHADER, PREFILTER_FRAGMENT_SHADER)?;
+            bind_sampler(gl, program, "environmentMap", glow::TEXTURE_CUBE_MAP, environment);
+            let resolution_loc = gl.get_uniform_location(program, "environmentSize");
+            gl.uniform_1_f32(resolution_loc.as_ref(), ENVIRONMENT_SIZE as f32);
+            let roughness_loc = gl.get_uniform_location(program, "roughness");
+            let rendered = (0..PREFILTER_MIPS).try_for_each(|mip| {
+                let roughness = mip as f32 / (PREFILTER_MIPS - 1) as f32;
+                render_cube_faces(gl, program, cube, prefiltered, PREFILTER_SIZE >> mip, mip, |gl| {
+                    gl.uniform_1_f32(roughness_loc.as_ref(), roughness);
+                })
+            });
+            gl.delete_program(program);
+            rendered?;
+
+            let brdf_lut = create_brdf_lut(gl)?;
+            maps.push(brdf_lut);
+            render_brdf_lut(gl, brdf_lut)?;
+            Ok(Self { environment, irradiance, prefiltered, brdf_lut })
+        })();
+
+        if let Some(framebuffer) = framebuffer {
+            gl.delete_framebuffer(framebuffer);
+        }
+        if let Some(cube) = cube {
+            cube.delete(gl);
+        }
+        gl.delete_texture(equirect);
+        if result.is_err() {
+            for map in maps {
+                gl.delete_texture(map);
+            }
+        }
+        result
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe {
+            for texture in [self.environment, self.irradiance, self.prefiltered, self.brdf_lut] {
+                gl.delete_texture(texture);
+            }
+        }
+    }
+
+    // Binds the maps to the units the model shader samples them from
+    pub fn bind(&self, gl: &glow::Context, prefiltered_unit: u32, irradiance_unit: u32, brdf_lut_unit: u32) {
+        unsafe {
+            for (unit, target, texture) in [
+                (prefiltered_unit, glow::TEXTURE_CUBE_MAP, self.prefiltered),
+                (irradiance_unit, glow::TEXTURE_CUBE_MAP, self.irradiance),
+                (brdf_lut_unit, glow::TEXTURE_2D, self.brdf_lut),
+            ] {
+                gl.active_texture(glow::TEXTURE0 + unit);
+                gl.bind_texture(target, Some(texture));
+            }
+        }
+    }
+}
+
+unsafe fn set_filtering(gl: &glow::Context, target: u32, min_filter: u32) {
+    gl.tex_parameter_i32(target, glow::TEXTURE_MIN_FILTER, min_filter as i32);
+    gl.tex_parameter_i32(target, glow::TEXTURE_MAG_FILTER, glow::LINEAR as i32);
+    gl.tex_parameter_i32(target, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE as i32);
+    gl.tex_parameter_i32(target, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
+    gl.tex_parameter_i32(target, glow::TEXTURE_WRAP_R, glow::CLAMP_TO_EDGE as i32);
+}
+
+unsafe fn bind_sampler(gl: &glow::Context, program: glow::Program, name: &str, target: u32, texture: glow::Texture) {
+    gl.use_program(Some(program));
+    gl.active_texture(glow::TEXTURE0);
+    gl.bind_texture(target, Some(texture));
+    let location = gl.get_uniform_location(program, name);
+    gl.uniform_1_i32(location.as_ref(), 0);
+}
+
+unsafe fn create_cubemap(gl: &glow::Context, size: i32, levels: i32) -> Result<glow::Texture, RendererError> {
+    let texture = gl.create_texture().map_err(RendererError::Gl)?;
+    gl.bind_texture(glow::TEXTURE_CUBE_MAP, Some(texture));
+    gl.tex_storage_2d(glow::TEXTURE_CUBE_MAP, levels, glow::RGBA16F, size, size);
+    set_filtering(gl, glow::TEXTURE_CUBE_MAP, if levels > 1 { glow::LINEAR_MIPMAP_LINEAR } else { glow::LINEAR });
+    Ok(texture)
+}
+
+// Renders the unit cube seen from its center into each face of the cubemap
+// level, with the program in use and set_uniforms called once
+unsafe fn render_cube_faces(
+    gl: &glow::Context,
+    program: glow::Program,
+    cube: glow::VertexArray,
+    cubemap: glow::Texture,
+    size: i32,
+    level: i32,
+    set_uniforms: impl FnOnce(&glow::Context),
+) -> Result<(), RendererError> {
+    gl.use_program(Some(program));
+    set_uniforms(gl);
+    let projection_loc = gl.get_uniform_location(program, "projection");
+    let view_loc = gl.get_uniform_location(program, "view");
+    let projection = Mat4::perspective_rh_gl(90f32.to_radians(), 1.0, 0.1, 10.0);
+    gl.uniform_matrix_4_f32_slice(projection_loc.as_ref(), false, &projection.to_cols_array());
+
+    gl.viewport(0, 0, size, size);
+    gl.disable(glow::DEPTH_TEST);
+    gl.bind_vertex_array(Some(cube));
+    for (face, view) in cube_face_views().iter().enumerate() {
+        gl.framebuffer_texture_2d(
+            glow::FRAMEBUFFER,
+            glow::COLOR_ATTACHMENT0,
+            glow::TEXTURE_CUBE_MAP_POSITIVE_X + face as u32,
+            Some(cubemap),
+            level,
+        );
+        check_framebuffer(gl)?;
+        gl.uniform_matrix_4_f32_slice(view_loc.as_ref(), false, &view.to_cols_array());
+        gl.draw_arrays(glow::TRIANGLES, 0, 36);
+    }
+    gl.enable(glow::DEPTH_TEST);
+    Ok(())
+}
+
+unsafe fn create_brdf_lut(gl: &glow::Context) -> Result<glow::Texture, RendererError> {
+    let texture = gl.create_texture().map_err(RendererError::Gl)?;
+    gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+    gl.tex_storage_2d(glow::TEXTURE_2D, 1, glow::RG16F, BRDF_LUT_SIZE, BRDF_LUT_SIZE);
+    set_filtering(gl, glow::TEXTURE_2D, glow::LINEAR);
+    Ok(texture)
+}
+
+unsafe fn render_brdf_lut(gl: &glow::Context, texture: glow::Texture) -> Result<(), RendererError> {
+    gl.framebuffer_texture_2d(glow::FRAMEBUFFER, glow::COLOR_ATTACHMENT0, glow::TEXTURE_2D, Some(texture), 0);
+    check_framebuffer(gl)?;
+
+    let program = utils::load_program(gl, FULLSCREEN_VERTEX_SHADER, BRDF_FRAGMENT_SHADER)?;
+    let empty = gl.create_vertex_array().map_err(RendererError::Gl);
+    if let Ok(empty) = empty {
+        gl.use_program(Some(program));
+        gl.viewport(0, 0, BRDF_LUT_SIZE, BRDF_LUT_SIZE);
+        gl.disable(glow::DEPTH_TEST);
+        gl.bind_vertex_array(Some(empty));
+        gl.draw_arrays(glow::TRIANGLES, 0, 3);
+        gl.enable(glow::DEPTH_TEST);
+        gl.delete_vertex_array(empty);
+    }
+    gl.delete_program(program);
+    empty.map(|_| ())
+}
+
+unsafe fn check_framebuffer(gl: &glow::Context) -> Result<(), RendererError> {
+    let status = gl.check_framebuffer_status(glow::FRAMEBUFFER);
+    if status != glow::FRAMEBUFFER_COMPLETE {
+        return Err(RendererError::Gl(format!("Floating point framebuffer is incomplete (status {:#x})", status)));
+    }
+    Ok(())
+}
+
+// Views from the origin through each cubemap face, in the face order of
+// TEXTURE_CUBE_MAP_POSITIVE_X onwards and with the up vectors cubemaps use
+fn cube_face_views() -> [Mat4; 6] {
+    [
+        (Vec3::X, Vec3::NEG_Y),
+        (Vec3::NEG_X, Vec3::NEG_Y),
+        (Vec3::Y, Vec3::Z),
+        (Vec3::NEG_Y, Vec3::NEG_Z),
+        (Vec3::Z, Vec3::NEG_Y),
+        (Vec3::NEG_Z, Vec3::NEG_Y),
+    ]
+    .map(|(forward, up)| Mat4::look_at_rh(Vec3::ZERO, forward, up))
+}
+
+// Unit cube of 36 positions at attribute 0, seen from the inside
+pub struct Cube {
+    pub vao: glow::VertexArray,
+    vbo: glow::Buffer,
+}
+
+impl Cube {
+    pub fn new(gl: &glow::Context) -> Result<Self, RendererError> {
+        let corners = utils::box_corners(Vec3::NEG_ONE, Vec3::ONE);
+        // Two triangles per face, as corner indices
+        const FACES: [[usize; 6]; 6] = [
+            [1, 3, 7, 1, 7, 5],
+            [0, 4, 6, 0, 6, 2],
+            [2, 6, 7, 2, 7, 3],
+            [0, 1, 5, 0, 5, 4],
+            [4, 5, 7, 4, 7, 6],
+            [0, 2, 3, 0, 3, 1],
+        ];
+        let positions: Vec<f32> = FACES
+            .iter()
+            .flatten()
+            .flat_map(|&corner| corners[corner].to_array())
+            .collect();
+
+        unsafe {
+            let vao = gl.create_vertex_array().map_err(RendererError::Gl)?;
+            let vbo = gl.create_buffer().map_err(RendererError::Gl)?;
+            gl.bind_vertex_array(Some(vao));
+            gl.bind_buffer(glow::ARRAY_BUFFER, Some(vbo));
+            gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, bytemuck::cast_slice(&positions), glow::STATIC_DRAW);
+            gl.enable_vertex_attrib_array(0);
+            gl.vertex_attrib_pointer_f32(0, 3, glow::FLOAT, false, 12, 0);
+            gl.bind_vertex_array(None);
+            Ok(Self { vao, vbo })
+        }
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_vertex_array(self.vao);
+            gl.delete_buffer(self.vbo);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn face_views_look_down_each_axis() {
+        let axes = [Vec3::X, Vec3::NEG_X, Vec3::Y, Vec3::NEG_Y, Vec3::Z, Vec3::NEG_Z];
+        for (view, axis) in cube_face_views().iter().zip(axes) {
+            assert!(view.transform_vector3(axis).abs_diff_eq(Vec3::NEG_Z, 1e-6), "{axis}");
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+use glam::Vec2;
+use winit::event::{DeviceEvent, ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{Key, KeyCode, ModifiersState, PhysicalKey};
+use winit::platform::modifier_supplement::KeyEventExtModifierSupplement;
+
+// Roughly one scroll line per 20 pixels of touchpad scrolling
+const PIXELS_PER_LINE: f32 = 20.0;
+
+// Keyboard and mouse state fed from window and device events. Held keys and
+// buttons persist until released, the deltas add up every event of a frame
+// and are cleared by end_frame().
+#[derive(Debug, Default)]
+pub struct InputState {
+    held_keys: HashSet<KeyCode>,
+    // Lowercase characters of the held keys in the current layout
+    held_characters: HashSet<String>,
+    held_buttons: HashSet<MouseButton>,
+    modifiers: ModifiersState,
+    // None until the cursor enters the window
+    cursor: Option<Vec2>,
+    cursor_delta: Vec2,
+    // Raw motion, also reported while the cursor is grabbed
+    mouse_motion: Vec2,
+    // In lines, positive away from the user
+    scroll: f32,
+}
+
+impl InputState {
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput { event, .. } => {
+                let pressed = event.state == ElementState::Pressed;
+                if let PhysicalKey::Code(code) = event.physical_key {
+                    self.set_key(code, pressed);
+                }
+                if let Key::Character(character) = event.key_without_modifiers() {
+                    if pressed {
+                        self.held_characters.insert(character.to_lowercase());
+                    } else {
+                        self.held_characters.remove(&character.to_lowercase());
+                    }
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                if *state == ElementState::Pressed {
+                    self.held_buttons.insert(*button);
+                } else {
+                    self.held_buttons.remove(button);
+                }
+            }
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers.state(),
+            WindowEvent::CursorMoved { position, .. } => {
+                self.move_cursor(Vec2::new(position.x as f32, position.y as f32));
+            }
+            WindowEvent::CursorLeft { .. } => self.cursor = None,
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+                };
+            }
+            // Releases would go to another window
+            WindowEvent::Focused(false) => {
+                self.held_keys.clear();
+                self.held_characters.clear();
+                self.held_buttons.clear();
+            }
+            _ => (),
+        }
+    }
+
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.mouse_motion += Vec2::new(delta.0 as f32, delta.1 as f32);
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        self.cursor_delta = Vec2::ZERO;
+        self.mouse_motion = Vec2::ZERO;
+        self.scroll = 0.0;
+    }
+
+    pub fn key_held(&self, code: KeyCode) -> bool {
+        self.held_keys.contains(&code)
+    }
+
+    pub fn character_held(&self, character: &str) -> bool {
+        self.held_characters.contains(character)
+    }
+
+    pub fn button_held(&self, button: MouseButton) -> bool {
+        self.held_buttons.contains(&button)
+    }
+
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    pub fn cursor_delta(&self) -> Vec2 {
+        self.cursor_delta
+    }
+
+    pub fn mouse_motion(&self) -> Vec2 {
+        self.mouse_motion
+    }
+
+    pub fn scroll(&self) -> f32 {
+        self.scroll
+    }
+
+    fn set_key(&mut self, code: KeyCode, pressed: bool) {
+        if pressed {
+            self.held_keys.insert(code);
+        } else {
+            self.held_keys.remove(&code);
+        }
+    }
+
+    fn move_cursor(&mut self, position: Vec2) {
+        if let Some(previous) = self.cursor {
+            self.cursor_delta += position - previous;
+        }
+        self.cursor = Some(position);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deltas_add_up_until_the_frame_ends() {
+        let mut input = InputState::default();
+        input.move_cursor(Vec2::new(10.0, 10.0));
+        input.move_cursor(Vec2::new(13.0, 9.0));
+        input.move_cursor(Vec2::new(15.0, 12.0));
+        input.set_key(KeyCode::KeyW, true);
+        assert_eq!(input.cursor_delta(), Vec2::new(5.0, 2.0));
+
+        input.end_frame();
+        assert_eq!(input.cursor_delta(), Vec2::ZERO);
+        assert!(input.key_held(KeyCode::KeyW));
+    }
+}
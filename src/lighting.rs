@@ -0,0 +1,166 @@
+use bytemuck::{Pod, Zeroable};
+use glow::HasContext;
+use crate::error::RendererError;
+use crate::scene::{Camera, Light, Shadow};
+use crate::shadows::{ShadowFrame, CASCADES, MAX_SHADOW_LAYERS};
+
+// Has to match MAX_LIGHTS in modelfragmentshader.glsl
+pub const MAX_LIGHTS: usize = 16;
+// Uniform buffer binding point of the Lights block
+const LIGHTS_BINDING: u32 = 0;
+
+const DIRECTIONAL: f32 = 0.0;
+const POINT: f32 = 1.0;
+const SPOT: f32 = 2.0;
+
+// One element of the std140 Lights block, every member is a vec4
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct GpuLight {
+    // xyz position, w light type
+    position_type: [f32; 4],
+    // xyz direction the light shines in, w range
+    direction_range: [f32; 4],
+    // rgb color, w intensity
+    color_intensity: [f32; 4],
+    // Cosines of the inner and outer cone angles
+    cone: [f32; 4],
+    // First shadow map layer or -1, bias, normal bias and layer count
+    shadow: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct LightBlock {
+    lights: [GpuLight; MAX_LIGHTS],
+    shadow_matrices: [[f32; 16]; MAX_SHADOW_LAYERS],
+    // One split per cascade, padded to a vec4
+    cascade_splits: [f32; 4],
+    // std140 pads the trailing int to a vec4
+    count: [i32; 4],
+}
+
+// Both are plain arrays of 4 byte numbers without padding
+unsafe impl Zeroable for GpuLight {}
+unsafe impl Pod for GpuLight {}
+unsafe impl Zeroable for LightBlock {}
+unsafe impl Pod for LightBlock {}
+
+impl Light {
+    // Lights scenes that don't have any, like the spotlight the shader used
+    // to mount on the camera
+    pub fn headlamp(camera: &Camera) -> Self {
+        Light::Spot {
+            position: camera.position(),
+            direction: -camera.direction(),
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+            range: 100.0,
+            inner_angle: 30.0,
+            outer_angle: 35.0,
+            shadow: None,
+        }
+    }
+
+    // layers are the first shadow map layer and layer count, if it has any
+    fn to_gpu(&self, layers: Option<(usize, usize)>) -> GpuLight {
+        let shadow = |shadow: &Option<Shadow>| match (shadow, layers) {
+            (Some(shadow), Some((first, count))) => [first as f32, shadow.bias, shadow.normal_bias, count as f32],
+            _ => [-1.0, 0.0, 0.0, 0.0],
+        };
+
+        match self {
+            Light::Directional { direction, color, intensity, shadow: settings } => GpuLight {
+                position_type: [0.0, 0.0, 0.0, DIRECTIONAL],
+                direction_range: direction.normalize_or_zero().extend(0.0).to_array(),
+                color_intensity: [color[0], color[1], color[2], *intensity],
+                cone: [0.0; 4],
+                shadow: shadow(settings),
+            },
+            Light::Point { position, color, intensity, range } => GpuLight {
+                position_type: position.extend(POINT).to_array(),
+                direction_range: [0.0, 0.0, 0.0, *range],
+                color_intensity: [color[0], color[1], color[2], *intensity],
+                cone: [0.0; 4],
+                shadow: shadow(&None),
+            },
+            Light::Spot { position, direction, color, intensity, range, inner_angle, outer_angle, shadow: settings } => GpuLight {
+                position_type: position.extend(SPOT).to_array(),
+                direction_range: direction.normalize_or_zero().extend(*range).to_array(),
+                color_intensity: [color[0], color[1], color[2], *intensity],
+                cone: [inner_angle.to_radians().cos(), outer_angle.to_radians().cos(), 0.0, 0.0],
+                shadow: shadow(settings),
+            },
+        }
+    }
+}
+
+// Uniform buffer holding the scene lights for the model shader
+pub struct LightBuffer {
+    buffer: glow::Buffer,
+}
+
+impl LightBuffer {
+    pub fn new(gl: &glow::Context) -> Result<Self, RendererError> {
+        unsafe {
+            let buffer = gl.create_buffer().map_err(RendererError::Gl)?;
+            gl.bind_buffer(glow::UNIFORM_BUFFER, Some(buffer));
+            gl.buffer_data_size(glow::UNIFORM_BUFFER, size_of::<LightBlock>() as i32, glow::DYNAMIC_DRAW);
+            gl.bind_buffer(glow::UNIFORM_BUFFER, None);
+            Ok(Self { buffer })
+        }
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_buffer(self.buffer);
+        }
+    }
+
+    // Shaders without a Lights block are left alone
+    pub fn bind_program(&self, gl: &glow::Context, program: glow::Program) {
+        unsafe {
+            if let Some(index) = gl.get_uniform_block_index(program, "Lights") {
+                gl.uniform_block_binding(program, index, LIGHTS_BINDING);
+            }
+        }
+    }
+
+    // Lights past MAX_LIGHTS are dropped, see RenderManager::with_target
+    pub fn upload(&self, gl: &glow::Context, lights: &[Light], shadows: &ShadowFrame) {
+        let mut block = LightBlock {
+            lights: [GpuLight::default(); MAX_LIGHTS],
+            shadow_matrices: [[0.0; 16]; MAX_SHADOW_LAYERS],
+            cascade_splits: [0.0; 4],
+            count: [0; 4],
+        };
+        for (i, (gpu_light, light)) in block.lights.iter_mut().zip(lights).enumerate() {
+            *gpu_light = light.to_gpu(shadows.layers.get(i).copied().flatten());
+        }
+        for (gpu_matrix, matrix) in block.shadow_matrices.iter_mut().zip(&shadows.matrices) {
+            *gpu_matrix = matrix.to_cols_array();
+        }
+        block.cascade_splits[..CASCADES].copy_from_slice(&shadows.cascade_splits);
+        block.count[0] = lights.len().min(MAX_LIGHTS) as i32;
+
+        unsafe {
+            gl.bind_buffer(glow::UNIFORM_BUFFER, Some(self.buffer));
+            gl.buffer_sub_data_u8_slice(glow::UNIFORM_BUFFER, 0, bytemuck::bytes_of(&block));
+            gl.bind_buffer(glow::UNIFORM_BUFFER, None);
+            gl.bind_buffer_base(glow::UNIFORM_BUFFER, LIGHTS_BINDING, Some(self.buffer));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_matches_std140_layout() {
+        // Five vec4s per light, a mat4 per shadow layer, then the splits and
+        // the count each padded to a vec4
+        assert_eq!(size_of::<GpuLight>(), 80);
+        assert_eq!(size_of::<LightBlock>(), 80 * MAX_LIGHTS + 64 * MAX_SHADOW_LAYERS + 16 + 16);
+    }
+}
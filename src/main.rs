@@ -1,16 +1,78 @@
+mod error;
+mod camera;
+mod input;
+mod bindings;
+mod cli;
 mod window_manager;
 mod render_manager;
 mod utils;
+mod material;
+mod gltf_loader;
+mod model;
+mod scene;
+mod scene_graph;
+mod lighting;
+mod shadows;
+mod ibl;
+mod skybox;
+mod ssao;
+mod tonemap;
+mod post;
+#[cfg(test)]
+mod golden;
 
+use std::path::Path;
 use winit::event_loop::{ControlFlow, EventLoop};
 use window_manager::App;
+use error::RendererError;
+use render_manager::RenderManager;
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let program = args.first().map(String::as_str).unwrap_or("OpenGl3D");
+    if cli::wants_help(&args[1..]) {
+        println!("{}", cli::usage(program));
+        return;
+    }
+
+    let mut options = match cli::parse(&args[1..]) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, cli::usage(program));
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = options.load_scene() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+
+    if let Some(output) = &options.headless {
+        if let Err(e) = render_headless(&options, output) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Err(e) = options.load_bindings() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
 
-    let mut app = App::default();
+    let mut app = App::new(options);
     if let Err(e) = event_loop.run_app(&mut app) {
         eprintln!("Error: {:?}", e);
     }
 }
+
+fn render_headless(options: &cli::Options, output: &Path) -> Result<(), RendererError> {
+    let (width, height) = options.window_size.unwrap_or(cli::HEADLESS_SIZE);
+    let mut render_manager = RenderManager::new_headless(width, height, &options.render)?;
+    let image = render_manager.render_offscreen()?;
+    utils::save_image(&image, output)
+}
@@ -0,0 +1,139 @@
+use std::path::Path;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::error::RendererError;
+use crate::utils;
+
+#[derive(Debug, Clone)]
+pub enum TextureSource {
+    File(String),
+    // Already decoded, e.g. embedded in a .glb. Rows are kept top to bottom as
+    // glTF expects, unlike files which are flipped for OBJ texture coordinates.
+    Image(Arc<image::RgbaImage>),
+}
+
+impl TextureSource {
+    pub fn exists(&self) -> bool {
+        match self {
+            TextureSource::File(path) => utils::get_asset_path(path).is_file(),
+            TextureSource::Image(_) => true,
+        }
+    }
+
+    pub fn load(&self) -> Result<image::RgbaImage, RendererError> {
+        match self {
+            TextureSource::File(path) => utils::get_image_data(path),
+            TextureSource::Image(image) => Ok((**image).clone()),
+        }
+    }
+}
+
+// Surface description of a mesh, as authored in an .mtl file.
+// Texture paths are relative to the asset directory, like every other path
+// passed to utils::get_asset_path.
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: String,
+    // Ka, Ni and illum are kept from the .mtl, though no shader input reads them
+    #[allow(dead_code)]
+    pub ambient: [f32; 3],
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    pub emissive: [f32; 3],
+    pub shininess: f32,
+    pub dissolve: f32,
+    #[allow(dead_code)]
+    pub optical_density: f32,
+    #[allow(dead_code)]
+    pub illumination_model: u8,
+    pub ambient_texture: Option<TextureSource>,
+    pub diffuse_texture: Option<TextureSource>,
+    pub specular_texture: Option<TextureSource>,
+    pub normal_texture: Option<TextureSource>,
+    pub emissive_texture: Option<TextureSource>,
+}
+
+impl Default for Material {
+    // White, so textures show through unchanged for models without an .mtl
+    fn default() -> Self {
+        Self {
+            name: String::from("default"),
+            ambient: [1.0, 1.0, 1.0],
+            diffuse: [1.0, 1.0, 1.0],
+            specular: [1.0, 1.0, 1.0],
+            emissive: [0.0, 0.0, 0.0],
+            shininess: 128.0,
+            dissolve: 1.0,
+            optical_density: 1.0,
+            illumination_model: 2,
+            ambient_texture: None,
+            diffuse_texture: None,
+            specular_texture: None,
+            normal_texture: None,
+            emissive_texture: None,
+        }
+    }
+}
+
+// Scene file changes to a loaded material. Only the factors can be
+// overridden, textures stay as loaded.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaterialOverride {
+    // Name of the material to change, every material of the model if missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub material: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diffuse: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub specular: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emissive: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shininess: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opacity: Option<f32>,
+}
+
+impl MaterialOverride {
+    pub fn matches(&self, material: &Material) -> bool {
+        self.material.as_ref().is_none_or(|name| *name == material.name)
+    }
+
+    pub fn apply(&self, material: &mut Material) {
+        material.diffuse = self.diffuse.unwrap_or(material.diffuse);
+        material.specular = self.specular.unwrap_or(material.specular);
+        material.emissive = self.emissive.unwrap_or(material.emissive);
+        material.shininess = self.shininess.unwrap_or(material.shininess);
+        material.dissolve = self.opacity.unwrap_or(material.dissolve);
+    }
+}
+
+impl Material {
+    pub fn from_mtl(material: &tobj::Material, base_dir: &Path) -> Self {
+        let default = Self::default();
+        // MTL texture statements may carry options like "-bm 1.0" before the file name
+        let texture = |path: &Option<String>| {
+            path.as_deref()
+                .and_then(|path| path.split_whitespace().last())
+                .map(|file| TextureSource::File(base_dir.join(file.replace('\\', "/")).to_string_lossy().into_owned()))
+        };
+
+        Self {
+            name: material.name.clone(),
+            ambient: material.ambient.unwrap_or(default.ambient),
+            diffuse: material.diffuse.unwrap_or(default.diffuse),
+            specular: material.specular.unwrap_or(default.specular),
+            emissive: material.emissive.unwrap_or(default.emissive),
+            shininess: material.shininess.unwrap_or(default.shininess),
+            dissolve: material.dissolve.unwrap_or(default.dissolve),
+            optical_density: material.optical_density.unwrap_or(default.optical_density),
+            illumination_model: material.illumination_model.unwrap_or(default.illumination_model),
+            ambient_texture: texture(&material.ambient_texture),
+            diffuse_texture: texture(&material.diffuse_texture),
+            specular_texture: texture(&material.specular_texture),
+            normal_texture: texture(&material.normal_texture),
+            emissive_texture: texture(&material.unknown_param.get("map_Ke").cloned()),
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use glam::{EulerRot, Mat4, Quat, Vec3};
+use glow::HasContext;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::error::RendererError;
+use crate::gltf_loader;
+use crate::material::{Material, MaterialOverride, TextureSource};
+use crate::utils;
+
+// A contiguous range of a model's index buffer drawn with one material
+#[derive(Debug, Clone, Copy)]
+pub struct Submesh {
+    pub index_offset: u32,
+    pub index_count: u32,
+    pub material: usize,
+}
+
+// CPU side geometry as returned by the loaders, indices are already offset
+// into the shared vertex buffer.
+pub struct MeshData {
+    pub vertices: Vec<f32>,
+    pub indices: Vec<u32>,
+    pub submeshes: Vec<Submesh>,
+    pub materials: Vec<Material>,
+}
+
+// Placement of a model in the world. Rotation is in degrees, applied X then
+// Y then Z.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Transform {
+    pub position: Vec3,
+    pub rotation: Vec3,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: Vec3::ZERO,
+            rotation: Vec3::ZERO,
+            scale: Vec3::ONE,
+        }
+    }
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Mat4 {
+        let rotation = Quat::from_euler(
+            EulerRot::XYZ,
+            self.rotation.x.to_radians(),
+            self.rotation.y.to_radians(),
+            self.rotation.z.to_radians(),
+        );
+        Mat4::from_scale_rotation_translation(self.scale, rotation, self.position)
+    }
+}
+
+pub struct MaterialTextures {
+    pub albedo: glow::Texture,
+    pub ao: glow::Texture,
+    pub metallic_smoothness: glow::Texture,
+    pub normal: glow::Texture,
+    pub emissive: glow::Texture,
+}
+
+// After the units of the lighting maps, has to match emissiveMap's place in
+// the sampler list of RenderManager
+pub const EMISSIVE_MAP_UNIT: u32 = 9;
+
+pub struct Model {
+    vao: glow::VertexArray,
+    // Vertex and index buffer
+    buffers: [glow::Buffer; 2],
+    // Axis aligned, in model space
+    bounds: (Vec3, Vec3),
+    submeshes: Vec<Submesh>,
+    materials: Vec<Material>,
+    textures: Vec<MaterialTextures>,
+}
+
+impl Model {
+    pub fn load(gl: &glow::Context, path: &str) -> Result<Self, RendererError> {
+        let mesh = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("gltf") | Some("glb") => gltf_loader::load_gltf_mesh(path)?,
+            _ => utils::load_mesh(path)?,
+        };
+
+        let texture_folder = find_texture_folder(path);
+        let texture_sources: Vec<TextureSource> = mesh.materials
+            .iter()
+            .flat_map(|material| texture_sources(path, material, texture_folder.as_deref()))
+            .collect();
+
+        let images: Vec<_> = utils::profile("images", || {texture_sources
+            .par_iter()
+            .map(|source| source.load())
+            .collect::<Result<_, _>>()})?;
+
+        let textures: Vec<_> = images
+            .into_iter()
+            .enumerate()
+            .map(|(i, image)| utils::generate_texture(gl, image, TEXTURE_MAPS[i % TEXTURE_MAPS.len()].3))
+            .collect::<Result<_, _>>()?;
+        let textures = textures
+            .chunks_exact(TEXTURE_MAPS.len())
+            .map(|maps| MaterialTextures {
+                albedo: maps[0],
+                ao: maps[1],
+                metallic_smoothness: maps[2],
+                normal: maps[3],
+                emissive: maps[4],
+            })
+            .collect();
+
+        let (vao, buffers) = upload_mesh(gl, &mesh.vertices, &mesh.indices)?;
+        Ok(Self {
+            vao,
+            buffers,
+            bounds: bounds(&mesh.vertices),
+            submeshes: mesh.submeshes,
+            materials: mesh.materials,
+            textures,
+        })
+    }
+
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        self.bounds
+    }
+
+    pub fn override_materials(&mut self, path: &str, overrides: &[MaterialOverride]) {
+        for material_override in overrides {
+            let mut matched = false;
+            for material in self.materials.iter_mut().filter(|material| material_override.matches(material)) {
+                material_override.apply(material);
+                matched = true;
+            }
+            if !matched {
+                eprintln!(
+                    "Warning: {} has no material {}, override ignored",
+                    path,
+                    material_override.material.as_deref().unwrap_or_default(),
+                );
+            }
+        }
+    }
+
+    // Expects the program to be in use with its samplers assigned to texture
+    // units 0 to 3 (albedo, ao, metallic/smoothness, normal) and
+    // EMISSIVE_MAP_UNIT.
+    pub fn draw(&self, gl: &glow::Context, shader_program: glow::Program) {
+        unsafe {
+            gl.bind_vertex_array(Some(self.vao));
+
+            for submesh in &self.submeshes {
+                let textures = &self.textures[submesh.material];
+                let units = [(0, textures.albedo), (1, textures.ao), (2, textures.metallic_smoothness), (3, textures.normal), (EMISSIVE_MAP_UNIT, textures.emissive)];
+                for (unit, texture) in units {
+                    gl.active_texture(glow::TEXTURE0 + unit);
+                    gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+                }
+                set_material_uniforms(gl, shader_program, &self.materials[submesh.material]);
+
+                gl.draw_elements(
+                    glow::TRIANGLES,
+                    submesh.index_count as i32,
+                    glow::UNSIGNED_INT,
+                    (submesh.index_offset as usize * std::mem::size_of::<u32>()) as i32,
+                );
+            }
+        }
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_vertex_array(self.vao);
+            for buffer in self.buffers {
+                gl.delete_buffer(buffer);
+            }
+            for textures in &self.textures {
+                for texture in [textures.albedo, textures.ao, textures.metallic_smoothness, textures.normal, textures.emissive] {
+                    gl.delete_texture(texture);
+                }
+            }
+        }
+    }
+
+    // Geometry only, for depth and normal passes that don't use materials
+    pub fn draw_depth(&self, gl: &glow::Context) {
+        unsafe {
+            gl.bind_vertex_array(Some(self.vao));
+            for submesh in &self.submeshes {
+                gl.draw_elements(
+                    glow::TRIANGLES,
+                    submesh.index_count as i32,
+                    glow::UNSIGNED_INT,
+                    (submesh.index_offset as usize * std::mem::size_of::<u32>()) as i32,
+                );
+            }
+        }
+    }
+}
+
+fn bounds(vertices: &[f32]) -> (Vec3, Vec3) {
+    let mut positions = vertices.chunks_exact(8).map(|vertex| Vec3::from_slice(&vertex[0..3]));
+    let first = positions.next().unwrap_or(Vec3::ZERO);
+    positions.fold((first, first), |(min, max), position| (min.min(position), max.max(position)))
+}
+
+// Name used in warnings, README file name, 1x1 fallback colour and whether
+// the map holds sRGB colors, for each map. Maps without a README name are
+// optional, their fallback leaves the material factor unchanged.
+const TEXTURE_MAPS: [(&str, Option<&str>, [u8; 4], bool); 5] = [
+    ("albedo", Some("AlbedoTransparency.png"), [255, 255, 255, 255], true),
+    ("ao", Some("AO.png"), [255, 255, 255, 255], false),
+    ("metallic/smoothness", Some("MetallicSmoothness.png"), [0, 0, 0, 0], false),
+    ("normal", Some("Normal.png"), [128, 128, 255, 255], false),
+    ("emissive", None, [255, 255, 255, 255], true),
+];
+
+// Maps named in the material win over the README naming scheme. There is no
+// metallic map in MTL, map_Ks is the closest thing to it. Maps that can't be
+// found are replaced by a neutral 1x1 texture instead of failing the load.
+fn texture_sources(path: &str, material: &Material, texture_folder: Option<&Path>) -> [TextureSource; 5] {
+    let authored = [
+        &material.diffuse_texture,
+        &material.ambient_texture,
+        &material.specular_texture,
+        &material.normal_texture,
+        &material.emissive_texture,
+    ];
+
+    let mut missing = Vec::new();
+    let sources = std::array::from_fn(|i| {
+        let (name, file_name, fallback, _) = TEXTURE_MAPS[i];
+        authored[i]
+            .clone()
+            .or_else(|| {
+                let file = find_case_insensitive(texture_folder?, file_name?)?;
+                Some(TextureSource::File(file.to_string_lossy().into_owned()))
+            })
+            .filter(|source| source.exists())
+            .unwrap_or_else(|| {
+                if file_name.is_some() {
+                    missing.push(name);
+                }
+                TextureSource::Image(Arc::new(image::RgbaImage::from_pixel(1, 1, image::Rgba(fallback))))
+            })
+    });
+
+    if !missing.is_empty() {
+        eprintln!(
+            "Warning: {} (material {}) is missing {} textures, using defaults",
+            path,
+            material.name,
+            missing.join(", "),
+        );
+    }
+    sources
+}
+
+// modelName.obj keeps its textures in modelNameTextures next to it, though
+// exporters don't agree on the capitalisation.
+fn find_texture_folder(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    let folder_name = format!("{}Textures", path.file_stem()?.to_string_lossy());
+    find_case_insensitive(path.parent()?, &folder_name)
+}
+
+fn find_case_insensitive(dir: &Path, name: &str) -> Option<PathBuf> {
+    std::fs::read_dir(utils::get_asset_path(&dir.to_string_lossy()))
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .find(|entry| entry.file_name().to_string_lossy().eq_ignore_ascii_case(name))
+        .map(|entry| dir.join(entry.file_name()))
+}
+
+fn set_material_uniforms(gl: &glow::Context, shader_program: glow::Program, material: &Material) {
+    unsafe {
+        let diffuse_loc = gl.get_uniform_location(shader_program, "materialDiffuse");
+        gl.uniform_3_f32_slice(diffuse_loc.as_ref(), &material.diffuse);
+        let specular_loc = gl.get_uniform_location(shader_program, "materialSpecular");
+        gl.uniform_3_f32_slice(specular_loc.as_ref(), &material.specular);
+        let emissive_loc = gl.get_uniform_location(shader_program, "materialEmissive");
+        gl.uniform_3_f32_slice(emissive_loc.as_ref(), &material.emissive);
+        let shininess_loc = gl.get_uniform_location(shader_program, "materialShininess");
+        gl.uniform_1_f32(shininess_loc.as_ref(), material.shininess);
+        let opacity_loc = gl.get_uniform_location(shader_program, "materialOpacity");
+        gl.uniform_1_f32(opacity_loc.as_ref(), material.dissolve);
+    }
+}
+
+fn upload_mesh(gl: &glow::Context, vertices: &[f32], indices: &[u32]) -> Result<(glow::VertexArray, [glow::Buffer; 2]), RendererError> {
+    unsafe {
+        let vao = gl.create_vertex_array().map_err(RendererError::Gl)?;
+        let vbo = gl.create_buffer().map_err(RendererError::Gl)?;
+        let ebo = gl.create_buffer().map_err(RendererError::Gl)?;
+        
+        gl.bind_vertex_array(Some(vao));
+
+        gl.bind_buffer(glow::ARRAY_BUFFER, Some(vbo));
+        gl.buffer_data_u8_slice(
+            glow::ARRAY_BUFFER,
+            bytemuck::cast_slice(vertices),
+            glow::STATIC_DRAW,
+        );
+
+        gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(ebo));
+        gl.buffer_data_u8_slice(
+            glow::ELEMENT_ARRAY_BUFFER,
+            bytemuck::cast_slice(indices),
+            glow::STATIC_DRAW,
+        );
+
+        const POSITION_ATTRIB: u32 = 0;
+        const NORMAL_ATTRIB: u32 = 1;
+        const TEXCOORD_ATTRIB: u32 = 2;
+
+        const VERTEX_SIZE: usize = std::mem::size_of::<f32>();
+        const STRIDE: i32 = (8 * VERTEX_SIZE) as i32; // 3 pos + 3 normal + 2 uv = 8 floats
+
+        const POSITION_OFFSET: i32 = 0;
+        const NORMAL_OFFSET: i32 = (3 * VERTEX_SIZE) as i32;
+        const TEXCOORD_OFFSET: i32 = (6 * VERTEX_SIZE) as i32;
+
+        gl.vertex_attrib_pointer_f32(
+            POSITION_ATTRIB,
+            3,  // vec3
+            glow::FLOAT,
+            false,
+            STRIDE,
+            POSITION_OFFSET
+        );
+
+        gl.vertex_attrib_pointer_f32(
+            NORMAL_ATTRIB,
+            3,  // vec3
+            glow::FLOAT,
+            false,
+            STRIDE,
+            NORMAL_OFFSET
+        );
+
+        gl.vertex_attrib_pointer_f32(
+            TEXCOORD_ATTRIB,
+            2,  // vec2
+            glow::FLOAT,
+            false,
+            STRIDE,
+            TEXCOORD_OFFSET
+        );
+
+        // Don't forget to enable all attribute arrays
+        gl.enable_vertex_attrib_array(POSITION_ATTRIB);
+        gl.enable_vertex_attrib_array(NORMAL_ATTRIB);
+        gl.enable_vertex_attrib_array(TEXCOORD_ATTRIB);
+
+        Ok((vao, [vbo, ebo]))
+    }
+}
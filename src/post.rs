@@ -0,0 +1,489 @@
+use std::collections::HashMap;
+use glow::HasContext;
+use serde::{Deserialize, Serialize};
+use crate::error::RendererError;
+use crate::ibl::FULLSCREEN_VERTEX_SHADER;
+use crate::scene::Tonemapping;
+use crate::tonemap::TonemapPass;
+use crate::utils;
+
+const BLOOM_DOWNSAMPLE_SHADER: &str = "shaders/bloomdownsamplefragmentshader.glsl";
+const BLOOM_UPSAMPLE_SHADER: &str = "shaders/bloomupsamplefragmentshader.glsl";
+const BLOOM_COMPOSITE_SHADER: &str = "shaders/bloomcompositefragmentshader.glsl";
+const FXAA_SHADER: &str = "shaders/fxaafragmentshader.glsl";
+const CHROMATIC_ABERRATION_SHADER: &str = "shaders/chromaticaberrationfragmentshader.glsl";
+const VIGNETTE_SHADER: &str = "shaders/vignettefragmentshader.glsl";
+const COLOR_GRADING_SHADER: &str = "shaders/colorgradingfragmentshader.glsl";
+
+// Halvings of the frame the bloom spreads over, fewer for small frames
+const BLOOM_LEVELS: usize = 6;
+
+// One pass of the post-processing chain, as listed in the scene file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PostEffect {
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub effect: Effect,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+// Bloom works on the HDR frame and runs before tonemapping, the others run
+// on the tonemapped frame in the order they are listed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Effect {
+    Bloom(Bloom),
+    Fxaa,
+    ChromaticAberration(ChromaticAberration),
+    Vignette(Vignette),
+    ColorGrading(ColorGrading),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bloom {
+    // Brightness where pixels start to glow, eased in over half of it
+    pub threshold: f32,
+    pub intensity: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self { threshold: 1.0, intensity: 0.05 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChromaticAberration {
+    // Offset of the red and blue channels at the corners, in screen widths
+    pub strength: f32,
+}
+
+impl Default for ChromaticAberration {
+    fn default() -> Self {
+        Self { strength: 0.004 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Vignette {
+    // How dark the corners get, 0 to 1
+    pub intensity: f32,
+    // Distance from the center where darkening ends and starts, 1 is a corner
+    pub radius: f32,
+    pub softness: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self { intensity: 0.4, radius: 1.0, softness: 0.6 }
+    }
+}
+
+// lut is a strip of square slices, N*N wide and N high: red grows left to
+// right in each slice, green top to bottom and blue from slice to slice
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColorGrading {
+    pub lut: String,
+    #[serde(default = "full_strength")]
+    pub strength: f32,
+}
+
+fn full_strength() -> f32 {
+    1.0
+}
+
+impl PostEffect {
+    // Same as the type in the scene file
+    pub fn name(&self) -> &'static str {
+        match self.effect {
+            Effect::Bloom(_) => "bloom",
+            Effect::Fxaa => "fxaa",
+            Effect::ChromaticAberration(_) => "chromatic_aberration",
+            Effect::Vignette(_) => "vignette",
+            Effect::ColorGrading(_) => "color_grading",
+        }
+    }
+}
+
+// Color texture with a framebuffer to render into it
+struct ColorTarget {
+    framebuffer: glow::Framebuffer,
+    texture: glow::Texture,
+    size: (i32, i32),
+}
+
+impl ColorTarget {
+    fn new(gl: &glow::Context, size: (i32, i32)) -> Result<Self, RendererError> {
+        unsafe {
+            let texture = gl.create_texture().map_err(RendererError::Gl)?;
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            gl.tex_storage_2d(glow::TEXTURE_2D, 1, glow::RGBA16F, size.0, size.1);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, glow::LINEAR as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, glow::LINEAR as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
+
+            let framebuffer = gl.create_framebuffer().map_err(RendererError::Gl)?;
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer));
+            gl.framebuffer_texture_2d(glow::FRAMEBUFFER, glow::COLOR_ATTACHMENT0, glow::TEXTURE_2D, Some(texture), 0);
+            let target = Self { framebuffer, texture, size };
+            let status = gl.check_framebuffer_status(glow::FRAMEBUFFER);
+            if status != glow::FRAMEBUFFER_COMPLETE {
+                target.delete(gl);
+                return Err(RendererError::Gl(format!("Post-processing framebuffer of {}x{} is incomplete (status {:#x})", size.0, size.1, status)));
+            }
+            Ok(target)
+        }
+    }
+
+    fn bind(&self, gl: &glow::Context) {
+        unsafe {
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.framebuffer));
+            gl.viewport(0, 0, self.size.0, self.size.1);
+        }
+    }
+
+    fn delete(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_framebuffer(self.framebuffer);
+            gl.delete_texture(self.texture);
+        }
+    }
+}
+
+// Intermediate images of one frame size
+struct Targets {
+    size: (u32, u32),
+    // Passes read one and write the other
+    ping_pong: [ColorTarget; 2],
+    // Half the frame size, then halving again
+    bloom_levels: Vec<ColorTarget>,
+}
+
+struct Programs {
+    bloom_downsample: glow::Program,
+    bloom_upsample: glow::Program,
+    bloom_composite: glow::Program,
+    fxaa: glow::Program,
+    chromatic_aberration: glow::Program,
+    vignette: glow::Program,
+    color_grading: glow::Program,
+}
+
+// Runs the post-processing chain and the tonemap pass between the HDR frame
+// and the framebuffer it ends up in
+pub struct PostStack {
+    tonemap: TonemapPass,
+    programs: Programs,
+    vao: glow::VertexArray,
+    targets: Option<Targets>,
+    // 3D textures of the color grading LUTs, by path
+    luts: HashMap<String, glow::Texture>,
+}
+
+impl PostStack {
+    // LUTs are loaded up front for every color grading pass, enabled or not
+    pub fn new(gl: &glow::Context, effects: &[PostEffect]) -> Result<Self, RendererError> {
+        let program = |fragment_shader| utils::load_program(gl, FULLSCREEN_VERTEX_SHADER, fragment_shader);
+        let programs = Programs {
+            bloom_downsample: program(BLOOM_DOWNSAMPLE_SHADER)?,
+            bloom_upsample: program(BLOOM_UPSAMPLE_SHADER)?,
+            bloom_composite: program(BLOOM_COMPOSITE_SHADER)?,
+            fxaa: program(FXAA_SHADER)?,
+            chromatic_aberration: program(CHROMATIC_ABERRATION_SHADER)?,
+            vignette: program(VIGNETTE_SHADER)?,
+            color_grading: program(COLOR_GRADING_SHADER)?,
+        };
+        unsafe {
+            gl.use_program(Some(programs.bloom_composite));
+            let bloom_loc = gl.get_uniform_location(programs.bloom_composite, "bloomImage");
+            gl.uniform_1_i32(bloom_loc.as_ref(), 1);
+            gl.use_program(Some(programs.color_grading));
+            let lut_loc = gl.get_uniform_location(programs.color_grading, "lut");
+            gl.uniform_1_i32(lut_loc.as_ref(), 1);
+        }
+
+        let mut luts = HashMap::new();
+        for effect in effects {
+            if let Effect::ColorGrading(grading) = &effect.effect {
+                if !luts.contains_key(&grading.lut) {
+                    luts.insert(grading.lut.clone(), load_lut(gl, &grading.lut)?);
+                }
+            }
+        }
+
+        let vao = unsafe { gl.create_vertex_array().map_err(RendererError::Gl)? };
+        let tonemap = TonemapPass::new(gl)?;
+        Ok(Self { tonemap, programs, vao, targets: None, luts })
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        self.tonemap.delete(gl);
+        let programs = &self.programs;
+        unsafe {
+            for program in [
+                programs.bloom_downsample,
+                programs.bloom_upsample,
+                programs.bloom_composite,
+                programs.fxaa,
+                programs.chromatic_aberration,
+                programs.vignette,
+                programs.color_grading,
+            ] {
+                gl.delete_program(program);
+            }
+            gl.delete_vertex_array(self.vao);
+            for &lut in self.luts.values() {
+                gl.delete_texture(lut);
+            }
+        }
+        if let Some(targets) = &self.targets {
+            targets.ping_pong.iter().chain(&targets.bloom_levels).for_each(|target| target.delete(gl));
+        }
+    }
+
+    // Post-processes the HDR frame in source and tonemaps it into output,
+    // a framebuffer of the given size
+    pub fn render(
+        &mut self,
+        gl: &glow::Context,
+        source: glow::Texture,
+        output: Option<glow::Framebuffer>,
+        size: (u32, u32),
+        effects: &[PostEffect],
+        tonemapping: &Tonemapping,
+    ) -> Result<(), RendererError> {
+        let enabled = || effects.iter().filter(|effect| effect.enabled).map(|effect| &effect.effect);
+        let display_effects: Vec<&Effect> = enabled().filter(|effect| !matches!(effect, Effect::Bloom(_))).collect();
+        let has_bloom = enabled().any(|effect| matches!(effect, Effect::Bloom(_)));
+        if !has_bloom && display_effects.is_empty() {
+            self.tonemap.draw(gl, source, tonemapping);
+            return Ok(());
+        }
+
+        if self.targets.as_ref().is_none_or(|targets| targets.size != size) {
+            if let Some(targets) = self.targets.take() {
+                targets.ping_pong.iter().chain(&targets.bloom_levels).for_each(|target| target.delete(gl));
+            }
+            self.targets = Some(create_targets(gl, size)?);
+        }
+        let targets = self.targets.as_ref().expect("created above");
+
+        unsafe {
+            gl.disable(glow::DEPTH_TEST);
+            gl.bind_vertex_array(Some(self.vao));
+        }
+
+        let mut source = source;
+        let mut next = 0;
+        for effect in enabled() {
+            if let Effect::Bloom(bloom) = effect {
+                self.bloom(gl, source, &targets.bloom_levels, &targets.ping_pong[next], bloom);
+                source = targets.ping_pong[next].texture;
+                next = 1 - next;
+            }
+        }
+
+        let passes = display_effects.len();
+        let bind_destination = |pass: usize, next: usize| {
+            if pass == passes {
+                unsafe {
+                    gl.bind_framebuffer(glow::FRAMEBUFFER, output);
+                    gl.viewport(0, 0, size.0 as i32, size.1 as i32);
+                }
+            } else {
+                targets.ping_pong[next].bind(gl);
+            }
+        };
+
+        bind_destination(0, next);
+        self.tonemap.draw(gl, source, tonemapping);
+        unsafe {
+            gl.disable(glow::DEPTH_TEST);
+            gl.bind_vertex_array(Some(self.vao));
+        }
+        for (pass, effect) in display_effects.into_iter().enumerate() {
+            source = targets.ping_pong[next].texture;
+            next = 1 - next;
+            bind_destination(pass + 1, next);
+            self.draw_effect(gl, source, size, effect);
+        }
+
+        unsafe {
+            gl.enable(glow::DEPTH_TEST);
+        }
+        Ok(())
+    }
+
+    // Blurs what is brighter than the threshold by halving the frame down the
+    // bloom levels and adding each back on the way up, then adds the result
+    // to the frame in destination
+    fn bloom(&self, gl: &glow::Context, source: glow::Texture, levels: &[ColorTarget], destination: &ColorTarget, bloom: &Bloom) {
+        let program = self.programs.bloom_downsample;
+        unsafe {
+            gl.use_program(Some(program));
+            let threshold_loc = gl.get_uniform_location(program, "threshold");
+            gl.uniform_1_f32(threshold_loc.as_ref(), bloom.threshold);
+            let prefilter_loc = gl.get_uniform_location(program, "prefilter");
+            let mut input = (source, destination.size);
+            for (level, target) in levels.iter().enumerate() {
+                gl.uniform_1_i32(prefilter_loc.as_ref(), (level == 0) as i32);
+                target.bind(gl);
+                draw_fullscreen(gl, program, input.0, input.1);
+                input = (target.texture, target.size);
+            }
+
+            let program = self.programs.bloom_upsample;
+            gl.use_program(Some(program));
+            gl.enable(glow::BLEND);
+            gl.blend_func(glow::ONE, glow::ONE);
+            for pair in levels.windows(2).rev() {
+                pair[0].bind(gl);
+                draw_fullscreen(gl, program, pair[1].texture, pair[1].size);
+            }
+            gl.disable(glow::BLEND);
+
+            let program = self.programs.bloom_composite;
+            gl.use_program(Some(program));
+            let intensity_loc = gl.get_uniform_location(program, "intensity");
+            gl.uniform_1_f32(intensity_loc.as_ref(), bloom.intensity);
+            gl.active_texture(glow::TEXTURE1);
+            gl.bind_texture(glow::TEXTURE_2D, levels.first().map(|level| level.texture));
+            destination.bind(gl);
+            draw_fullscreen(gl, program, source, destination.size);
+        }
+    }
+
+    fn draw_effect(&self, gl: &glow::Context, source: glow::Texture, size: (u32, u32), effect: &Effect) {
+        let size = (size.0 as i32, size.1 as i32);
+        unsafe {
+            let program = match effect {
+                Effect::Bloom(_) => return,
+                Effect::Fxaa => self.programs.fxaa,
+                Effect::ChromaticAberration(settings) => {
+                    let program = self.programs.chromatic_aberration;
+                    gl.use_program(Some(program));
+                    let strength_loc = gl.get_uniform_location(program, "strength");
+                    gl.uniform_1_f32(strength_loc.as_ref(), settings.strength);
+                    program
+                }
+                Effect::Vignette(settings) => {
+                    let program = self.programs.vignette;
+                    gl.use_program(Some(program));
+                    for (name, value) in [("intensity", settings.intensity), ("radius", settings.radius), ("softness", settings.softness)] {
+                        let location = gl.get_uniform_location(program, name);
+                        gl.uniform_1_f32(location.as_ref(), value);
+                    }
+                    program
+                }
+                Effect::ColorGrading(settings) => {
+                    let program = self.programs.color_grading;
+                    gl.use_program(Some(program));
+                    let strength_loc = gl.get_uniform_location(program, "strength");
+                    gl.uniform_1_f32(strength_loc.as_ref(), settings.strength);
+                    gl.active_texture(glow::TEXTURE1);
+                    gl.bind_texture(glow::TEXTURE_3D, self.luts.get(&settings.lut).copied());
+                    program
+                }
+            };
+            draw_fullscreen(gl, program, source, size);
+        }
+    }
+}
+
+// Draws the fullscreen triangle with source on unit 0, source_size is the
+// size of source in pixels
+unsafe fn draw_fullscreen(gl: &glow::Context, program: glow::Program, source: glow::Texture, source_size: (i32, i32)) {
+    gl.use_program(Some(program));
+    gl.active_texture(glow::TEXTURE0);
+    gl.bind_texture(glow::TEXTURE_2D, Some(source));
+    let texel_loc = gl.get_uniform_location(program, "texelSize");
+    gl.uniform_2_f32(texel_loc.as_ref(), 1.0 / source_size.0 as f32, 1.0 / source_size.1 as f32);
+    gl.draw_arrays(glow::TRIANGLES, 0, 3);
+}
+
+fn create_targets(gl: &glow::Context, size: (u32, u32)) -> Result<Targets, RendererError> {
+    let full_size = (size.0 as i32, size.1 as i32);
+    let ping_pong = [ColorTarget::new(gl, full_size)?, ColorTarget::new(gl, full_size)?];
+    let mut bloom_levels = Vec::new();
+    let mut level_size = full_size;
+    while bloom_levels.len() < BLOOM_LEVELS && level_size.0 >= 2 && level_size.1 >= 2 {
+        level_size = (level_size.0 / 2, level_size.1 / 2);
+        bloom_levels.push(ColorTarget::new(gl, level_size)?);
+    }
+    Ok(Targets { size, ping_pong, bloom_levels })
+}
+
+// Turns the LUT strip into a 3D texture so the hardware blends between
+// slices
+fn load_lut(gl: &glow::Context, path: &str) -> Result<glow::Texture, RendererError> {
+    let asset_path = utils::get_asset_path(path);
+    let image = image::open(&asset_path)
+        .map_err(|source| RendererError::Image { path: asset_path.clone(), source })?
+        .into_rgba8();
+    let lut_size = image.height();
+    if lut_size < 2 || image.width() != lut_size * lut_size {
+        return Err(RendererError::Asset {
+            path: asset_path,
+            message: format!("a LUT strip must be N*N wide and N high, got {}x{}", image.width(), image.height()),
+        });
+    }
+
+    let mut voxels = Vec::with_capacity(image.as_raw().len());
+    for blue in 0..lut_size {
+        for green in 0..lut_size {
+            for red in 0..lut_size {
+                voxels.extend_from_slice(&image.get_pixel(blue * lut_size + red, green).0);
+            }
+        }
+    }
+
+    unsafe {
+        let texture = gl.create_texture().map_err(RendererError::Gl)?;
+        gl.bind_texture(glow::TEXTURE_3D, Some(texture));
+        gl.tex_image_3d(
+            glow::TEXTURE_3D,
+            0,
+            glow::RGBA8 as i32,
+            lut_size as i32,
+            lut_size as i32,
+            lut_size as i32,
+            0,
+            glow::RGBA,
+            glow::UNSIGNED_BYTE,
+            glow::PixelUnpackData::Slice(Some(&voxels)),
+        );
+        gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_MIN_FILTER, glow::LINEAR as i32);
+        gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_MAG_FILTER, glow::LINEAR as i32);
+        for wrap in [glow::TEXTURE_WRAP_S, glow::TEXTURE_WRAP_T, glow::TEXTURE_WRAP_R] {
+            gl.tex_parameter_i32(glow::TEXTURE_3D, wrap, glow::CLAMP_TO_EDGE as i32);
+        }
+        Ok(texture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn effects_are_tagged_by_type() {
+        let effects: Vec<PostEffect> = serde_json::from_str(
+            r#"[{"type": "bloom", "intensity": 0.1}, {"type": "fxaa", "enabled": false}, {"type": "color_grading", "lut": "luts/warm.png"}]"#,
+        )
+        .unwrap();
+        assert_eq!(effects[0].effect, Effect::Bloom(Bloom { intensity: 0.1, ..Default::default() }));
+        assert!(effects[0].enabled && !effects[1].enabled);
+        assert_eq!(effects[2].name(), "color_grading");
+
+        let json = serde_json::to_string(&effects).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<PostEffect>>(&json).unwrap(), effects);
+    }
+}
@@ -1,85 +1,235 @@
 use glutin::{
-    config::ConfigTemplateBuilder,
+    api::egl,
+    config::{Config, ConfigSurfaceTypes, ConfigTemplateBuilder},
     context::{ContextApi, ContextAttributesBuilder, NotCurrentGlContext},
     display::{Display, DisplayApiPreference},
     prelude::*,
-    surface::{Surface, WindowSurface},
+    surface::{Surface, SwapInterval, WindowSurface},
 };
 use glow::HasContext;
-use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+use raw_window_handle::{HasDisplayHandle, HasWindowHandle, RawWindowHandle};
 use winit::window::Window;
 use std::ffi::CString;
 use glam::{Vec3, Mat4};
+use crate::error::RendererError;
+use crate::ibl::{Ibl, PREFILTER_MIPS};
+use crate::lighting::{LightBuffer, MAX_LIGHTS};
+use crate::model::Model;
+use crate::post::PostStack;
+use crate::scene::{Light, Scene, Sky};
+use crate::shadows::{ShadowFrame, ShadowMaps};
+use crate::skybox::{self, Skybox};
+use crate::ssao::Ssao;
+use crate::tonemap::HdrTarget;
 use crate::utils;
 
+pub const DEFAULT_MODEL: &str = "objs/Guitar_01_OBJ/Guitar_01.obj";
+pub const DEFAULT_VERTEX_SHADER: &str = "shaders/modelvertexshader.glsl";
+pub const DEFAULT_FRAGMENT_SHADER: &str = "shaders/modelfragmentshader.glsl";
+
+const NEAR_PLANE: f32 = 0.1;
+const FAR_PLANE: f32 = 100.0;
+const ENVIRONMENT_MAP_UNIT: u32 = 4;
+const SHADOW_MAP_UNIT: u32 = 5;
+const IRRADIANCE_MAP_UNIT: u32 = 6;
+const BRDF_LUT_UNIT: u32 = 7;
+const SSAO_UNIT: u32 = 8;
+
+// How the model shader lights surfaces. Legacy is the Blinn-Phong look the
+// renderer started with, kept for comparison.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShadingModel {
+    Legacy,
+    #[default]
+    PhysicallyBased,
+}
+
+impl ShadingModel {
+    pub fn toggle(self) -> Self {
+        match self {
+            ShadingModel::Legacy => ShadingModel::PhysicallyBased,
+            ShadingModel::PhysicallyBased => ShadingModel::Legacy,
+        }
+    }
+}
+
+impl std::str::FromStr for ShadingModel {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value {
+            "legacy" => Ok(ShadingModel::Legacy),
+            "pbr" => Ok(ShadingModel::PhysicallyBased),
+            _ => Err(format!("Unknown shading model {:?}, expected legacy or pbr", value)),
+        }
+    }
+}
+
+impl std::fmt::Display for ShadingModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ShadingModel::Legacy => write!(f, "legacy"),
+            ShadingModel::PhysicallyBased => write!(f, "pbr"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderConfig {
+    pub scene: Scene,
+    pub vertex_shader: String,
+    pub fragment_shader: String,
+    // Samples of the HDR target the scene is lit into, headless or not
+    pub msaa_samples: Option<u8>,
+    // Screenshots and headless frames are rendered this many times larger
+    // and scaled down
+    pub ssaa: u32,
+    pub vsync: bool,
+    pub shading: ShadingModel,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            scene: Scene::sample(),
+            vertex_shader: DEFAULT_VERTEX_SHADER.to_owned(),
+            fragment_shader: DEFAULT_FRAGMENT_SHADER.to_owned(),
+            msaa_samples: None,
+            ssaa: 1,
+            vsync: true,
+            shading: ShadingModel::default(),
+        }
+    }
+}
+
+enum RenderTarget {
+    Window {
+        surface: Surface<WindowSurface>,
+        context: glutin::context::PossiblyCurrentContext,
+    },
+    Offscreen {
+        // Kept alive for as long as the framebuffer is in use.
+        _context: egl::context::PossiblyCurrentContext,
+        framebuffer: utils::Framebuffer,
+    },
+}
+
 pub struct RenderManager {
     gl: glow::Context,
-    surface: Surface<WindowSurface>,
-    context: glutin::context::PossiblyCurrentContext,
+    target: RenderTarget,
 	shader_program: glow::Program,
-	vaos: Vec<glow::VertexArray>,
-	//start_time: std::time::Instant,
-    nums_of_indices: Vec<i32>,
+	lights: LightBuffer,
+	shadow_maps: ShadowMaps,
+	// Only when the scene has an environment map
+	ibl: Option<Ibl>,
+	skybox: Skybox,
+	ssao: Ssao,
+	post: PostStack,
+	// Created on the first frame and whenever the size changes
+	hdr_target: Option<HdrTarget>,
+	// Of the HDR target
+	msaa_samples: u8,
+	ssaa: u32,
+	// Indexed by Node::mesh
+	models: Vec<Model>,
+	scene: Scene,
+	shading: ShadingModel,
+}
+
+#[cfg(windows)]
+fn display_preference(raw_window_handle: RawWindowHandle) -> DisplayApiPreference {
+    DisplayApiPreference::WglThenEgl(Some(raw_window_handle))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn display_preference(_: RawWindowHandle) -> DisplayApiPreference {
+    // EGL covers both Wayland and X11; GLX is only there for older X11 drivers.
+    DisplayApiPreference::EglThenGlx(Box::new(winit::platform::x11::register_xlib_error_hook))
+}
+
+#[cfg(target_os = "macos")]
+fn display_preference(_: RawWindowHandle) -> DisplayApiPreference {
+    DisplayApiPreference::Cgl
+}
+
+// glutin and raw-window-handle errors only carry a message worth keeping
+fn context_error(action: &'static str, message: impl std::fmt::Display) -> RendererError {
+    RendererError::Context { action, message: message.to_string() }
+}
+
+// The window only receives the tonemapped frame, a fullscreen triangle from
+// the already resolved HDR target, so it is single sampled whatever --msaa says
+fn find_config(display: &Display, raw_window_handle: RawWindowHandle) -> Result<Config, RendererError> {
+    let template = ConfigTemplateBuilder::new()
+        .with_alpha_size(8)
+        .with_depth_size(24)
+        .with_stencil_size(8)
+        .compatible_with_native_window(raw_window_handle)
+        .build();
+
+    let configs = unsafe {
+        display
+            .find_configs(template)
+            .map_err(|e| context_error("Failed to find configs", e))?
+    };
+    configs
+        .min_by_key(|config| (config.num_samples(), config.depth_size() < 24, config.stencil_size() < 8))
+        .ok_or_else(|| context_error("Failed to find configs", "no config matches the window"))
 }
 
 impl RenderManager {    
-    pub fn new(window: &Window) -> Self {
-        let template = ConfigTemplateBuilder::new()
-            .with_alpha_size(8)
-            .build();
+    pub fn new(window: &Window, config: &RenderConfig) -> Result<Self, RendererError> {
+        let raw_window_handle = window.window_handle()
+            .map_err(|e| context_error("Failed to get window handle", e))?
+            .as_raw();
 
         let display = unsafe {
             Display::new(
                 window.display_handle()
-                    .map_err(|e| e.to_string())
-                    .unwrap()
+                    .map_err(|e| context_error("Failed to get display handle", e))?
                     .as_raw(),
-                DisplayApiPreference::Wgl(None)
+                display_preference(raw_window_handle)
             )
-            .expect("Failed to create display")
+            .map_err(|e| context_error("Failed to create display", e))?
         };
 
-        let config = unsafe {
-            display
-                .find_configs(template)
-                .expect("Failed to find configs")
-                .next()
-                .expect("No config found")
-        };
+        let gl_config = find_config(&display, raw_window_handle)?;
+        println!("{}", utils::describe_config(&display, &gl_config));
 
         let context_attributes = ContextAttributesBuilder::new()
             .with_context_api(ContextApi::OpenGl(None))
-            .build(Some(
-                window.window_handle()
-                    .map_err(|e| e.to_string())
-                    .unwrap()
-                    .as_raw()
-            ));
+            .build(Some(raw_window_handle));
 
         let size = window.inner_size();
         let surface_attributes = 
             glutin::surface::SurfaceAttributesBuilder::<WindowSurface>::new().build(
-                window.window_handle()
-                    .map_err(|e| e.to_string())
-                    .unwrap()
-                    .as_raw(),
-                std::num::NonZeroU32::new(size.width).unwrap(),
-                std::num::NonZeroU32::new(size.height).unwrap(),
+                raw_window_handle,
+                std::num::NonZeroU32::new(size.width.max(1)).unwrap(),
+                std::num::NonZeroU32::new(size.height.max(1)).unwrap(),
             );
 
         let surface = unsafe {
             display
-                .create_window_surface(&config, &surface_attributes)
-                .expect("Failed to create surface")
+                .create_window_surface(&gl_config, &surface_attributes)
+                .map_err(|e| context_error("Failed to create surface", e))?
         };
 
         let context = unsafe {
             display
-                .create_context(&config, &context_attributes)
-                .expect("Failed to create context")
+                .create_context(&gl_config, &context_attributes)
+                .map_err(|e| context_error("Failed to create context", e))?
                 .make_current(&surface)
-                .expect("Failed to make context current")
+                .map_err(|e| context_error("Failed to make context current", e))?
+        };
+
+        let interval = if config.vsync {
+            SwapInterval::Wait(std::num::NonZeroU32::MIN)
+        } else {
+            SwapInterval::DontWait
         };
+        if let Err(e) = surface.set_swap_interval(&context, interval) {
+            eprintln!("Failed to set vsync to {}: {}", config.vsync, e);
+        }
 
         let gl = unsafe {
             glow::Context::from_loader_function(|s| {
@@ -88,48 +238,271 @@ impl RenderManager {
             })
         };
         
-        let vertex_source = utils::load_shader("shaders/modelvertexshader.glsl");
-        let fragment_source = utils::load_shader("shaders/modelfragmentshader.glsl");
+        Self::with_target(gl, RenderTarget::Window { surface, context }, config)
+    }
+
+    pub fn new_headless(width: u32, height: u32, config: &RenderConfig) -> Result<Self, RendererError> {
+        let device = egl::device::Device::query_devices()
+            .map_err(|e| context_error("Failed to query EGL devices", e))?
+            .next()
+            .ok_or_else(|| context_error("Failed to query EGL devices", "no EGL device found"))?;
+
+        let display = unsafe {
+            egl::display::Display::with_device(&device, None)
+                .map_err(|e| context_error("Failed to create EGL display", e))?
+        };
+
+        let template = ConfigTemplateBuilder::new()
+            .with_alpha_size(8)
+            .with_surface_type(ConfigSurfaceTypes::empty())
+            .build();
+
+        let gl_config = unsafe {
+            display
+                .find_configs(template)
+                .map_err(|e| context_error("Failed to find configs", e))?
+                .next()
+                .ok_or_else(|| context_error("Failed to find configs", "no surfaceless config found"))?
+        };
+        println!("{}", utils::describe_config(&display, &gl_config));
+
+        let context_attributes = ContextAttributesBuilder::new()
+            .with_context_api(ContextApi::OpenGl(None))
+            .build(None);
+
+        let context = unsafe {
+            display
+                .create_context(&gl_config, &context_attributes)
+                .map_err(|e| context_error("Failed to create context", e))?
+                .make_current_surfaceless()
+                .map_err(|e| context_error("Failed to make context current", e))?
+        };
+
+        let gl = unsafe {
+            glow::Context::from_loader_function(|s| {
+                let s = CString::new(s).unwrap();
+                display.get_proc_address(s.as_c_str()) as *const _
+            })
+        };
+
+        let framebuffer = utils::create_framebuffer(&gl, width, height)?;
+
+        Self::with_target(gl, RenderTarget::Offscreen { _context: context, framebuffer }, config)
+    }
+
+    fn with_target(gl: glow::Context, target: RenderTarget, config: &RenderConfig) -> Result<Self, RendererError> {
         // Create shader program first
-        let vertex_shader = utils::compile_shader(&gl, &vertex_source, glow::VERTEX_SHADER);
-        let fragment_shader = utils::compile_shader(&gl, &fragment_source, glow::FRAGMENT_SHADER);
-        let shader_program = utils::create_shader_program(&gl, vertex_shader, fragment_shader);
+        let shader_program = utils::load_program(&gl, &config.vertex_shader, &config.fragment_shader)?;
         
-        let mut vaos: Vec<glow::VertexArray>;
-        let mut nums_of_indices: Vec<i32>;
+        unsafe {
+            gl.use_program(Some(shader_program));
+            // Samplers of different types must not share a texture unit
+            for (unit, name) in ["albedoMap", "aoMap", "metallicSmoothnessMap", "normalMap", "environmentMap", "shadowMaps", "irradianceMap", "brdfLut", "ssaoMap", "emissiveMap"].iter().enumerate() {
+                let location = gl.get_uniform_location(shader_program, name);
+                gl.uniform_1_i32(location.as_ref(), unit as i32);
+            }
+        }
 
-        let (mut vao, mut num_indices) = utils::load_model_with_textures(&gl, &shader_program, "objs/Guitar_01_OBJ/Guitar_01.obj");
-        vaos = vec![vao];
-        nums_of_indices = vec![num_indices];
-        (vao, num_indices) = utils::load_model_with_textures(&gl, &shader_program, "objs/Guitar_01_OBJ/Guitar_01.obj");
-        vaos.push(vao);
-        nums_of_indices.push(num_indices);
+        let lights = LightBuffer::new(&gl)?;
+        lights.bind_program(&gl, shader_program);
 
-        Self {
+        let mut scene = config.scene.clone();
+        if scene.lights.len() > MAX_LIGHTS {
+            eprintln!("Warning: the scene has {} lights, only the first {} are rendered", scene.lights.len(), MAX_LIGHTS);
+        }
+        let shadow_maps = ShadowMaps::new(&gl, &scene.lights[..scene.lights.len().min(MAX_LIGHTS)])?;
+        let ibl = match &scene.environment.map {
+            Some(path) => Some(utils::profile("environment", || Ibl::load(&gl, path))?),
+            None => None,
+        };
+        if scene.environment.sky == Some(Sky::Map) && ibl.is_none() {
+            eprintln!("Warning: the sky needs an environment map, clearing to the background color instead");
+        }
+        let skybox = Skybox::new(&gl)?;
+        let ssao = Ssao::new(&gl)?;
+        let post = PostStack::new(&gl, &scene.post)?;
+        let max_samples = unsafe { gl.get_parameter_i32(glow::MAX_SAMPLES) }.clamp(1, u8::MAX as i32) as u8;
+        let msaa_samples = config.msaa_samples.unwrap_or(0);
+        if msaa_samples > max_samples {
+            eprintln!("Warning: {} MSAA samples requested, the driver supports at most {}", msaa_samples, max_samples);
+        }
+        let mut models = Vec::new();
+        scene.visit_mut(|node| {
+            if let Some(path) = &node.model {
+                let mut model = Model::load(&gl, path)?;
+                model.override_materials(path, &node.material_overrides);
+                node.mesh = Some(models.len());
+                models.push(model);
+            }
+            Ok::<_, RendererError>(())
+        })?;
+
+        Ok(Self {
             gl,
-            surface,
-            context,
+            target,
             shader_program,
-            vaos,
-            //start_time: std::time::Instant::now(),
-            nums_of_indices,
-		}
+            lights,
+            shadow_maps,
+            ibl,
+            skybox,
+            ssao,
+            post,
+            hdr_target: None,
+            msaa_samples: msaa_samples.min(max_samples),
+            ssaa: config.ssaa.max(1),
+            models,
+            scene,
+            shading: config.shading,
+		})
     }
 
-    pub fn render(&self, size: (u32, u32), mouse: (f64, f64), scroll: f64, modelpos: (f32, f32), camera: (f32, f32)) {
-       
-        //let time = self.start_time.elapsed().as_secs_f32();
-        
-        let camera_pos = Vec3::new(0.0, 0.0, 5.0 * scroll as f32);
-        
-        let y_rot = camera.0 as f32 * 0.5;
-        let x_rot = camera.1 as f32 * 0.5;
+    pub fn shading(&self) -> ShadingModel {
+        self.shading
+    }
+
+    pub fn set_shading(&mut self, shading: ShadingModel) {
+        self.shading = shading;
+    }
+
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    // Nodes can be moved or hidden, but models are only loaded on creation
+    pub fn scene_mut(&mut self) -> &mut Scene {
+        &mut self.scene
+    }
+
+    // World space box around every visible model
+    pub fn scene_bounds(&mut self) -> Option<(Vec3, Vec3)> {
+        self.scene.update_world();
+        self.world_bounds()
+    }
+
+    // Same as scene_bounds(), with the world matrices already up to date
+    fn world_bounds(&self) -> Option<(Vec3, Vec3)> {
+        let mut bounds: Option<(Vec3, Vec3)> = None;
+        self.scene.visit_visible(|node| {
+            let Some(mesh) = node.mesh else {
+                return;
+            };
+            let (min, max) = self.models[mesh].bounds();
+            for corner in utils::box_corners(min, max) {
+                let corner = node.world.transform_point3(corner);
+                bounds = Some(match bounds {
+                    Some((min, max)) => (min.min(corner), max.max(corner)),
+                    None => (corner, corner),
+                });
+            }
+        });
+        bounds
+    }
+
+    // EGL window surfaces (e.g. on Wayland) keep the size they were created
+    // with until told otherwise. Minimized windows report 0x0, which a
+    // surface can't have, they are resized once they come back.
+    pub fn resize(&self, size: (u32, u32)) {
+        let (Some(width), Some(height)) = (std::num::NonZeroU32::new(size.0), std::num::NonZeroU32::new(size.1)) else {
+            return;
+        };
+        if let RenderTarget::Window { surface, context } = &self.target {
+            surface.resize(context, width, height);
+        }
+    }
+
+    // A minimized window has a size of 0x0, there is nothing to draw or
+    // present until it comes back
+    pub fn render(&mut self, size: (u32, u32)) -> Result<(), RendererError> {
+        if size.0 == 0 || size.1 == 0 {
+            return Ok(());
+        }
+        self.draw(size)?;
+
+        if let RenderTarget::Window { surface, context } = &self.target {
+            surface
+                .swap_buffers(context)
+                .map_err(|e| context_error("Failed to swap buffers", e))?;
+        }
+        Ok(())
+    }
+
+    pub fn render_offscreen(&mut self) -> Result<image::RgbaImage, RendererError> {
+        let RenderTarget::Offscreen { framebuffer, .. } = &self.target else {
+            panic!("render_offscreen requires a headless RenderManager");
+        };
+
+        self.capture(framebuffer.size, 1)
+    }
+
+    /// Draws the current frame again and reads it back without presenting it.
+    /// The image is `scale` times larger than `size`. A `scale` or SSAA factor
+    /// above 1 renders into a temporary framebuffer, which SSAA makes larger
+    /// still and is scaled down to the image size afterwards.
+    pub fn capture(&mut self, size: (u32, u32), scale: u32) -> Result<image::RgbaImage, RendererError> {
+        let default_framebuffer = match &self.target {
+            RenderTarget::Window { .. } => None,
+            RenderTarget::Offscreen { framebuffer, .. } => Some(framebuffer.framebuffer),
+        };
+
+        let ssaa = self.ssaa_factor((size.0 * scale, size.1 * scale));
+        let supersample = scale * ssaa;
+        if supersample <= 1 {
+            unsafe {
+                self.gl.bind_framebuffer(glow::FRAMEBUFFER, default_framebuffer);
+            }
+            self.draw(size)?;
+            return Ok(utils::read_pixels(&self.gl, size));
+        }
+
+        let size = (size.0 * supersample, size.1 * supersample);
+        let framebuffer = utils::create_framebuffer(&self.gl, size.0, size.1).inspect_err(|_| unsafe {
+            self.gl.bind_framebuffer(glow::FRAMEBUFFER, default_framebuffer);
+        })?;
+        let image = self.draw(size).map(|()| utils::downsample(&utils::read_pixels(&self.gl, size), ssaa));
+
+        unsafe {
+            self.gl.bind_framebuffer(glow::FRAMEBUFFER, default_framebuffer);
+        }
+        framebuffer.delete(&self.gl);
+        image
+    }
 
-        let camera_direction = Vec3::new(
-            y_rot.sin() * x_rot.cos(),
-            -x_rot.sin(),
-            y_rot.cos() * x_rot.cos()
-        ).normalize();
+    // The configured SSAA factor, lowered until a frame of size times the
+    // factor fits into the largest framebuffer the driver supports
+    fn ssaa_factor(&self, size: (u32, u32)) -> u32 {
+        let max_size = unsafe {
+            self.gl.get_parameter_i32(glow::MAX_RENDERBUFFER_SIZE).min(self.gl.get_parameter_i32(glow::MAX_TEXTURE_SIZE))
+        }.max(1) as u32;
+        let largest = size.0.max(size.1);
+        let mut ssaa = self.ssaa;
+        while ssaa > 1 && largest * ssaa > max_size {
+            ssaa -= 1;
+        }
+        if ssaa != self.ssaa {
+            eprintln!("Warning: lowering SSAA from {}x to {}x to stay within the {} pixel framebuffer limit", self.ssaa, ssaa, max_size);
+        }
+        ssaa
+    }
+
+    // Lights the scene into the HDR target, then post-processes and tonemaps
+    // it into the framebuffer that was bound
+    fn draw(&mut self, size: (u32, u32)) -> Result<(), RendererError> {
+        // Every target below would be reallocated at a size GL rejects
+        if size.0 == 0 || size.1 == 0 {
+            return Ok(());
+        }
+        let output = unsafe { self.gl.get_parameter_framebuffer(glow::DRAW_FRAMEBUFFER_BINDING) };
+        if self.hdr_target.as_ref().is_none_or(|target| target.size != size) {
+            if let Some(target) = self.hdr_target.take() {
+                target.delete(&self.gl);
+            }
+            self.hdr_target = Some(HdrTarget::new(&self.gl, size, self.msaa_samples)?);
+        }
+        self.scene.update_world();
+
+        let camera = &self.scene.camera;
+        let camera_pos = camera.position();
+        let camera_direction = camera.direction();
 
         let view_matrix = Mat4::look_at_rh(
             camera_pos,
@@ -138,14 +511,33 @@ impl RenderManager {
         );
 
         //let projection_matrix = Mat4::orthographic_lh(-5.0, 5.0, -5.0, 5.0, 0.1, 100.0);
+        let aspect = size.0 as f32 / size.1 as f32;
         let projection_matrix = Mat4::perspective_rh(
-            45.0_f32.to_radians(),
-            size.0 as f32 / size.1 as f32,
-            0.1,
-            100.0,
+            camera.fov.to_radians(),
+            aspect,
+            NEAR_PLANE,
+            FAR_PLANE,
         );
+
+        let bounds = self.world_bounds();
+        let shadows = self.shadow_maps.render(&self.gl, &self.scene, &self.models, aspect, (NEAR_PLANE, FAR_PLANE), bounds);
+        let use_ssao = self.scene.ambient_occlusion.enabled;
+        if use_ssao {
+            self.ssao.render(&self.gl, size, &self.scene, &self.models, view_matrix, projection_matrix)?;
+        }
+
+        let camera = &self.scene.camera;
+        let environment = &self.scene.environment;
+        let hdr_target = self.hdr_target.as_ref().expect("created above");
+        hdr_target.bind(&self.gl);
         unsafe {
-            
+            // A headless render is a single frame, so the program and clear
+            // state have to be set up before anything is uploaded or cleared.
+            self.gl.use_program(Some(self.shader_program));
+            let [red, green, blue] = environment.background;
+            self.gl.clear_color(red, green, blue, 1.0);
+            self.gl.viewport(0, 0, (size.0) as i32, (size.1) as i32);
+
             let model_loc = self.gl.get_uniform_location(self.shader_program, "model");
             let view_loc = self.gl.get_uniform_location(self.shader_program, "view");
             let proj_loc = self.gl.get_uniform_location(self.shader_program, "projection");
@@ -168,43 +560,80 @@ impl RenderManager {
                 camera_pos.y,
                 camera_pos.z,);
             self.gl.enable(glow::DEPTH_TEST);
-            let camera_dir_loc = self.gl.get_uniform_location(self.shader_program, "cameraDir");
-            self.gl.uniform_3_f32(
-                camera_dir_loc.as_ref(),
-                camera_direction.x,
-                camera_direction.y,
-                camera_direction.z,);
+            if self.scene.lights.is_empty() {
+                self.lights.upload(&self.gl, &[Light::headlamp(camera)], &ShadowFrame::default());
+            } else {
+                self.lights.upload(&self.gl, &self.scene.lights, &shadows);
+            }
+            self.shadow_maps.bind_texture(&self.gl, SHADOW_MAP_UNIT);
+            let use_ssao_loc = self.gl.get_uniform_location(self.shader_program, "useSsao");
+            self.gl.uniform_1_i32(use_ssao_loc.as_ref(), use_ssao as i32);
+            if use_ssao {
+                self.ssao.bind_texture(&self.gl, SSAO_UNIT);
+            }
+            let ambient_loc = self.gl.get_uniform_location(self.shader_program, "ambientIntensity");
+            self.gl.uniform_1_f32(ambient_loc.as_ref(), environment.ambient_intensity);
+            let physically_based_loc = self.gl.get_uniform_location(self.shader_program, "physicallyBased");
+            self.gl.uniform_1_i32(physically_based_loc.as_ref(), (self.shading == ShadingModel::PhysicallyBased) as i32);
+            let use_environment_loc = self.gl.get_uniform_location(self.shader_program, "useEnvironment");
+            self.gl.uniform_1_i32(use_environment_loc.as_ref(), self.ibl.is_some() as i32);
+            if let Some(ibl) = &self.ibl {
+                ibl.bind(&self.gl, ENVIRONMENT_MAP_UNIT, IRRADIANCE_MAP_UNIT, BRDF_LUT_UNIT);
+                let intensity_loc = self.gl.get_uniform_location(self.shader_program, "environmentIntensity");
+                self.gl.uniform_1_f32(intensity_loc.as_ref(), environment.intensity);
+                let rotation_loc = self.gl.get_uniform_location(self.shader_program, "environmentRotation");
+                self.gl.uniform_matrix_3_f32_slice(rotation_loc.as_ref(), false, &skybox::environment_rotation(environment).to_cols_array());
+                let max_lod_loc = self.gl.get_uniform_location(self.shader_program, "environmentMaxLod");
+                self.gl.uniform_1_f32(max_lod_loc.as_ref(), (PREFILTER_MIPS - 1) as f32);
+            }
 
             self.gl.clear(glow::COLOR_BUFFER_BIT | glow::DEPTH_BUFFER_BIT);
-           
-            self.gl.viewport(0, 0, (size.0) as i32, (size.1) as i32);
-            
-            self.gl.use_program(Some(self.shader_program));
-
-            self.gl.clear_color(0.0, 0.0, 0.0, 1.0);
             
-            for i in 0..self.vaos.len() {
-
-                let rotation = Mat4::from_rotation_y(mouse.0 as f32 * 0.005) * Mat4::from_rotation_x(mouse.1 as f32 * 0.005);
-                let translation = Mat4::from_translation(Vec3::new(modelpos.0 + 2.5  - 5.0 * i as f32, modelpos.1, 0.0));
-                let model_matrix = rotation * translation;
-
+            self.scene.visit_visible(|node| {
+                let Some(mesh) = node.mesh else {
+                    return;
+                };
                 self.gl.uniform_matrix_4_f32_slice(
                     model_loc.as_ref(),
                     false,
-                    &model_matrix.to_cols_array(),
-                );
-                
-                self.gl.bind_vertex_array(Some(self.vaos[i]));
-                self.gl.draw_elements(
-                    glow::TRIANGLES,
-                    self.nums_of_indices[i],
-                    glow::UNSIGNED_INT,
-                    0,
+                    &node.world.to_cols_array(),
                 );
-            }
-            
-            self.surface.swap_buffers(&self.context).unwrap();
+                self.models[mesh].draw(&self.gl, self.shader_program);
+            });
+        }
+
+        self.skybox.draw(&self.gl, view_matrix, projection_matrix, environment, self.ibl.as_ref());
+
+        hdr_target.resolve(&self.gl);
+        unsafe {
+            self.gl.bind_framebuffer(glow::FRAMEBUFFER, output);
         }
+        self.post.render(&self.gl, hdr_target.texture(), output, size, &self.scene.post, &self.scene.tonemapping)
     }
-}
\ No newline at end of file
+}
+
+// Runs before the fields are dropped, while the context is still current
+impl Drop for RenderManager {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.delete_program(self.shader_program);
+        }
+        self.lights.delete(&self.gl);
+        self.shadow_maps.delete(&self.gl);
+        self.ssao.delete(&self.gl);
+        self.post.delete(&self.gl);
+        for model in &self.models {
+            model.delete(&self.gl);
+        }
+        self.skybox.delete(&self.gl);
+        if let Some(ibl) = &self.ibl {
+            ibl.delete(&self.gl);
+        }
+        if let Some(target) = &self.hdr_target {
+            target.delete(&self.gl);
+        }
+        if let RenderTarget::Offscreen { framebuffer, .. } = &self.target {
+            framebuffer.delete(&self.gl);
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use std::fs;
+use glam::{Mat4, Vec3};
+use serde::{Deserialize, Serialize};
+use crate::error::RendererError;
+use crate::model::Transform;
+use crate::post::PostEffect;
+use crate::render_manager::DEFAULT_MODEL;
+use crate::scene_graph::Node;
+use crate::utils;
+
+// Everything needed to reproduce what the app shows, stored as JSON. Paths
+// in the file and the file itself are relative to the asset directory.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scene {
+    // Root nodes of the scene graph
+    pub nodes: Vec<Node>,
+    pub lights: Vec<Light>,
+    pub camera: Camera,
+    pub environment: Environment,
+    pub tonemapping: Tonemapping,
+    pub ambient_occlusion: AmbientOcclusion,
+    // Post-processing passes, see post.rs for the order they run in
+    pub post: Vec<PostEffect>,
+}
+
+// Scenes without lights are lit by Light::headlamp(), a spotlight mounted on
+// the camera. Directions point the way the light shines.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Light {
+    Directional {
+        direction: Vec3,
+        color: [f32; 3],
+        intensity: f32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        shadow: Option<Shadow>,
+    },
+    Point {
+        position: Vec3,
+        color: [f32; 3],
+        intensity: f32,
+        range: f32,
+    },
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        color: [f32; 3],
+        intensity: f32,
+        range: f32,
+        // Cone angles in degrees, full intensity inside the inner one
+        inner_angle: f32,
+        outer_angle: f32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        shadow: Option<Shadow>,
+    },
+}
+
+// Lights with shadow settings cast shadows. The biases trade shadow acne on
+// lit surfaces against shadows detaching from their casters.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Shadow {
+    // Subtracted from the depth compared against the shadow map, in 0..1 depth
+    pub bias: f32,
+    // Moves the looked up position along the surface normal, in world units
+    pub normal_bias: f32,
+}
+
+impl Default for Shadow {
+    fn default() -> Self {
+        Self {
+            bias: 0.001,
+            normal_bias: 0.02,
+        }
+    }
+}
+
+// Looks at target from distance away, in the direction given by yaw and
+// pitch in degrees. Zero yaw and pitch looks down -Z.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Camera {
+    pub target: Vec3,
+    pub distance: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fov: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            target: Vec3::ZERO,
+            distance: 5.0,
+            yaw: 0.0,
+            pitch: 0.0,
+            fov: 45.0,
+        }
+    }
+}
+
+impl Camera {
+    pub fn position(&self) -> Vec3 {
+        self.target + self.direction() * self.distance
+    }
+
+    // Points from the target back towards the camera
+    pub fn direction(&self) -> Vec3 {
+        let (yaw, pitch) = (self.yaw.to_radians(), self.pitch.to_radians());
+        Vec3::new(
+            yaw.sin() * pitch.cos(),
+            -pitch.sin(),
+            yaw.cos() * pitch.cos()
+        ).normalize()
+    }
+}
+
+// Without a map the scene gets the flat ambient term. With one, an
+// equirectangular .hdr image, it replaces the ambient term and lights the
+// scene scaled by intensity. Scenes without a sky are cleared to background.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Environment {
+    pub background: [f32; 3],
+    pub ambient_intensity: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub map: Option<String>,
+    pub intensity: f32,
+    // Degrees around the Y axis, turns the map for both lighting and the sky
+    pub rotation: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sky: Option<Sky>,
+    // Brightness of the sky in stops, doesn't change the lighting
+    pub sky_exposure: f32,
+}
+
+// What is drawn behind the models
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Sky {
+    // The environment map, needs Environment::map
+    Map,
+    // Blends from the horizon up to the zenith and down to the ground
+    Gradient {
+        zenith: [f32; 3],
+        horizon: [f32; 3],
+        ground: [f32; 3],
+    },
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            background: [0.0, 0.0, 0.0],
+            ambient_intensity: 0.1,
+            map: None,
+            intensity: 1.0,
+            rotation: 0.0,
+            sky: None,
+            sky_exposure: 0.0,
+        }
+    }
+}
+
+// How the HDR frame is brought into display range
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tonemapping {
+    pub operator: TonemapOperator,
+    // In stops, applied before the operator
+    pub exposure: f32,
+}
+
+// Screen space ambient occlusion, darkens the ambient and environment light
+// in creases and where models meet on top of any baked AO map
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AmbientOcclusion {
+    pub enabled: bool,
+    // How far around a point geometry can occlude it, in world units
+    pub radius: f32,
+    // Exponent on the unoccluded fraction, 0 turns the darkening off
+    pub intensity: f32,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            radius: 0.5,
+            intensity: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TonemapOperator {
+    Reinhard,
+    #[default]
+    Aces,
+    Agx,
+}
+
+impl TonemapOperator {
+    pub fn next(self) -> Self {
+        match self {
+            TonemapOperator::Reinhard => TonemapOperator::Aces,
+            TonemapOperator::Aces => TonemapOperator::Agx,
+            TonemapOperator::Agx => TonemapOperator::Reinhard,
+        }
+    }
+}
+
+impl Scene {
+    // Two guitars side by side, shown when neither a scene nor models are given
+    pub fn sample() -> Self {
+        let guitar = |x: f32| Node::with_model(DEFAULT_MODEL, Transform {
+            position: Vec3::new(x, 0.0, 0.0),
+            ..Default::default()
+        });
+
+        Self {
+            nodes: vec![guitar(2.5), guitar(-2.5)],
+            ..Default::default()
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, RendererError> {
+        let path = utils::get_asset_path(path);
+        let json = fs::read_to_string(&path)
+            .map_err(|source| RendererError::Io { path: path.clone(), source })?;
+        let scene: Self = serde_json::from_str(&json).map_err(|source| RendererError::Scene { path: path.clone(), source })?;
+        scene.validate().map_err(|message| RendererError::Asset { path, message })?;
+        Ok(scene)
+    }
+
+    // Values that parse but can't be rendered
+    fn validate(&self) -> Result<(), String> {
+        for (i, light) in self.lights.iter().enumerate() {
+            if let Light::Point { range, .. } | Light::Spot { range, .. } = light {
+                if *range < 0.0 {
+                    return Err(format!("light {} has a negative range ({})", i, range));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn update_world(&mut self) {
+        for node in &mut self.nodes {
+            node.update_world(Mat4::IDENTITY);
+        }
+    }
+
+    pub fn visit_visible<'a>(&'a self, mut f: impl FnMut(&'a Node)) {
+        for node in &self.nodes {
+            node.visit_visible(&mut f);
+        }
+    }
+
+    pub fn visit_mut<E>(&mut self, mut f: impl FnMut(&mut Node) -> Result<(), E>) -> Result<(), E> {
+        for node in &mut self.nodes {
+            node.visit_mut(&mut f)?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), RendererError> {
+        let path = utils::get_asset_path(path);
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|source| RendererError::Scene { path: path.clone(), source })?;
+        fs::write(&path, json + "\n").map_err(|source| RendererError::Io { path, source })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_scene_round_trips() {
+        let scene = Scene::load("scenes/guitars.json").unwrap();
+        let json = serde_json::to_string_pretty(&scene).unwrap();
+        assert_eq!(serde_json::from_str::<Scene>(&json).unwrap(), scene);
+    }
+
+    #[test]
+    fn sky_is_tagged_by_type() {
+        let environment: Environment = serde_json::from_str(r#"{"sky": {"type": "map"}, "rotation": 90}"#).unwrap();
+        assert_eq!(environment.sky, Some(Sky::Map));
+        assert_eq!(environment.rotation, 90.0);
+        assert!(serde_json::from_str::<Environment>(r#"{"sky": {"type": "gradient"}}"#).is_err());
+    }
+
+    #[test]
+    fn negative_ranges_are_rejected() {
+        let scene: Scene = serde_json::from_str(
+            r#"{"lights": [{"type": "point", "position": [0, 1, 0], "color": [1, 1, 1], "intensity": 1, "range": -5}]}"#,
+        ).unwrap();
+        assert_eq!(scene.validate().unwrap_err(), "light 0 has a negative range (-5)");
+        assert!(Scene::sample().validate().is_ok());
+    }
+
+    #[test]
+    fn missing_fields_use_defaults() {
+        let scene: Scene = serde_json::from_str(r#"{"nodes": [{"model": "objs/monkey.obj"}]}"#).unwrap();
+        assert_eq!(scene.nodes[0].transform, Transform::default());
+        assert!(scene.nodes[0].visible);
+        assert_eq!(scene.camera, Camera::default());
+        assert_eq!(scene.environment, Environment::default());
+    }
+}
@@ -0,0 +1,115 @@
+use glam::Mat4;
+use serde::{Deserialize, Serialize};
+use crate::material::MaterialOverride;
+use crate::model::Transform;
+
+// A node of the scene graph. The transform is relative to the parent node,
+// world is the product of all transforms up to the root and is only valid
+// after update_world().
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Node {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    pub transform: Transform,
+    // Model file drawn at this node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub material_overrides: Vec<MaterialOverride>,
+    // Hides the node and everything below it
+    pub visible: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Node>,
+    #[serde(skip)]
+    pub world: Mat4,
+    // Index of the loaded model, set by the RenderManager
+    #[serde(skip)]
+    pub mesh: Option<usize>,
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            transform: Transform::default(),
+            model: None,
+            material_overrides: Vec::new(),
+            visible: true,
+            children: Vec::new(),
+            world: Mat4::IDENTITY,
+            mesh: None,
+        }
+    }
+}
+
+impl Node {
+    pub fn with_model(path: &str, transform: Transform) -> Self {
+        Self {
+            model: Some(path.to_owned()),
+            transform,
+            ..Default::default()
+        }
+    }
+
+    pub fn update_world(&mut self, parent_world: Mat4) {
+        self.world = parent_world * self.transform.matrix();
+        for child in &mut self.children {
+            child.update_world(self.world);
+        }
+    }
+
+    // Depth first, parents before their children, skipping hidden subtrees
+    pub fn visit_visible<'a>(&'a self, f: &mut impl FnMut(&'a Node)) {
+        if !self.visible {
+            return;
+        }
+        f(self);
+        for child in &self.children {
+            child.visit_visible(f);
+        }
+    }
+
+    // Every node, hidden or not
+    pub fn visit_mut<E>(&mut self, f: &mut impl FnMut(&mut Node) -> Result<(), E>) -> Result<(), E> {
+        f(self)?;
+        for child in &mut self.children {
+            child.visit_mut(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    fn node(name: &str, position: Vec3, children: Vec<Node>) -> Node {
+        Node {
+            name: name.to_owned(),
+            transform: Transform { position, ..Default::default() },
+            children,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn world_matrices_include_parents() {
+        let mut root = node("root", Vec3::X, vec![node("child", Vec3::Y, vec![node("grandchild", Vec3::Z, vec![])])]);
+        root.update_world(Mat4::from_translation(Vec3::splat(10.0)));
+
+        let grandchild = &root.children[0].children[0];
+        assert_eq!(grandchild.world.transform_point3(Vec3::ZERO), Vec3::new(11.0, 11.0, 11.0));
+    }
+
+    #[test]
+    fn hidden_nodes_hide_their_subtree() {
+        let mut root = node("root", Vec3::ZERO, vec![node("hidden", Vec3::ZERO, vec![node("below", Vec3::ZERO, vec![])]), node("shown", Vec3::ZERO, vec![])]);
+        root.children[0].visible = false;
+
+        let mut visited = Vec::new();
+        root.visit_visible(&mut |node| visited.push(node.name.as_str()));
+        assert_eq!(visited, ["root", "shown"]);
+    }
+}
@@ -0,0 +1,251 @@
+use glam::{Mat4, Vec3};
+use glow::HasContext;
+use crate::error::RendererError;
+use crate::model::Model;
+use crate::scene::{Camera, Light, Scene};
+use crate::utils;
+
+// Have to match the shadowMatrices and cascadeSplits sizes in
+// modelfragmentshader.glsl
+pub const MAX_SHADOW_LAYERS: usize = 8;
+pub const CASCADES: usize = 3;
+
+const SHADOW_VERTEX_SHADER: &str = "shaders/shadowvertexshader.glsl";
+const SHADOW_FRAGMENT_SHADER: &str = "shaders/shadowfragmentshader.glsl";
+const SHADOW_MAP_SIZE: i32 = 2048;
+// Directional shadows end this far from the camera
+const SHADOW_DISTANCE: f32 = 40.0;
+// Blend between logarithmic (1) and even (0) cascade splits
+const CASCADE_LAMBDA: f32 = 0.75;
+const SPOT_NEAR_PLANE: f32 = 0.05;
+
+// Where this frame's shadow maps are, for the light uniform buffer
+#[derive(Debug, Default)]
+pub struct ShadowFrame {
+    // First layer and layer count of each light, None for lights without shadows
+    pub layers: Vec<Option<(usize, usize)>>,
+    // World to shadow map clip space of every layer
+    pub matrices: Vec<Mat4>,
+    // View space depth where each cascade ends
+    pub cascade_splits: [f32; CASCADES],
+}
+
+// Depth maps of every shadow casting light, as layers of one texture array.
+// Directional lights take one layer per cascade, spot lights one layer.
+pub struct ShadowMaps {
+    program: glow::Program,
+    texture: glow::Texture,
+    framebuffer: glow::Framebuffer,
+    layers: Vec<Option<(usize, usize)>>,
+    layer_count: usize,
+}
+
+impl ShadowMaps {
+    // Layers are assigned once, lights can move afterwards but not start or
+    // stop casting shadows
+    pub fn new(gl: &glow::Context, lights: &[Light]) -> Result<Self, RendererError> {
+        let mut layer_count = 0;
+        let layers = lights
+            .iter()
+            .map(|light| {
+                let count = match light {
+                    Light::Directional { shadow: Some(_), .. } => CASCADES,
+                    Light::Spot { shadow: Some(_), .. } => 1,
+                    _ => return None,
+                };
+                if layer_count + count > MAX_SHADOW_LAYERS {
+                    eprintln!("Warning: out of shadow maps, a light won't cast shadows ({} layers at most)", MAX_SHADOW_LAYERS);
+                    return None;
+                }
+                layer_count += count;
+                Some((layer_count - count, count))
+            })
+            .collect();
+
+        let program = utils::load_program(gl, SHADOW_VERTEX_SHADER, SHADOW_FRAGMENT_SHADER)?;
+        unsafe {
+            // The model shader samples the array even without shadows
+            let size = if layer_count == 0 { 1 } else { SHADOW_MAP_SIZE };
+            let texture = gl.create_texture().map_err(RendererError::Gl)?;
+            gl.bind_texture(glow::TEXTURE_2D_ARRAY, Some(texture));
+            gl.tex_storage_3d(glow::TEXTURE_2D_ARRAY, 1, glow::DEPTH_COMPONENT24, size, size, layer_count.max(1) as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D_ARRAY, glow::TEXTURE_MIN_FILTER, glow::LINEAR as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D_ARRAY, glow::TEXTURE_MAG_FILTER, glow::LINEAR as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D_ARRAY, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D_ARRAY, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
+            // Hardware comparison, LINEAR filtering then blends 2x2 results
+            gl.tex_parameter_i32(glow::TEXTURE_2D_ARRAY, glow::TEXTURE_COMPARE_MODE, glow::COMPARE_REF_TO_TEXTURE as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D_ARRAY, glow::TEXTURE_COMPARE_FUNC, glow::LEQUAL as i32);
+
+            let framebuffer = gl.create_framebuffer().map_err(RendererError::Gl)?;
+            Ok(Self { program, texture, framebuffer, layers, layer_count })
+        }
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_program(self.program);
+            gl.delete_texture(self.texture);
+            gl.delete_framebuffer(self.framebuffer);
+        }
+    }
+
+    pub fn bind_texture(&self, gl: &glow::Context, unit: u32) {
+        unsafe {
+            gl.active_texture(glow::TEXTURE0 + unit);
+            gl.bind_texture(glow::TEXTURE_2D_ARRAY, Some(self.texture));
+        }
+    }
+
+    // Renders the depth of every visible model into the layers of each shadow
+    // casting light. clip_planes are the near and far planes of the camera,
+    // bounds the world space box around the scene. Leaves the framebuffer that
+    // was bound before.
+    pub fn render(&self, gl: &glow::Context, scene: &Scene, models: &[Model], aspect: f32, clip_planes: (f32, f32), bounds: Option<(Vec3, Vec3)>) -> ShadowFrame {
+        let (near, far) = clip_planes;
+        let cascade_splits = cascade_splits(near, far.min(SHADOW_DISTANCE));
+        let mut frame = ShadowFrame {
+            layers: self.layers.clone(),
+            matrices: vec![Mat4::IDENTITY; self.layer_count],
+            cascade_splits,
+        };
+
+        for (light, layers) in scene.lights.iter().zip(&self.layers) {
+            let Some((first, _)) = *layers else {
+                continue;
+            };
+            // A zero direction lights nothing, see GpuLight, so there is no
+            // shadow to render either
+            match *light {
+                Light::Directional { direction, .. } => {
+                    let Some(direction) = direction.try_normalize() else {
+                        continue;
+                    };
+                    for cascade in 0..CASCADES {
+                        let slice_near = if cascade == 0 { near } else { cascade_splits[cascade - 1] };
+                        frame.matrices[first + cascade] =
+                            cascade_matrix(&scene.camera, aspect, slice_near, cascade_splits[cascade], direction, bounds);
+                    }
+                }
+                Light::Spot { position, direction, range, outer_angle, .. } => {
+                    let Some(direction) = direction.try_normalize() else {
+                        continue;
+                    };
+                    frame.matrices[first] = spot_matrix(position, direction, outer_angle, range);
+                }
+                Light::Point { .. } => (),
+            }
+        }
+
+        if frame.matrices.is_empty() {
+            return frame;
+        }
+
+        unsafe {
+            let previous_framebuffer = gl.get_parameter_framebuffer(glow::DRAW_FRAMEBUFFER_BINDING);
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.framebuffer));
+            gl.draw_buffers(&[glow::NONE]);
+            gl.read_buffer(glow::NONE);
+            gl.viewport(0, 0, SHADOW_MAP_SIZE, SHADOW_MAP_SIZE);
+            gl.enable(glow::DEPTH_TEST);
+            gl.use_program(Some(self.program));
+
+            let light_matrix_loc = gl.get_uniform_location(self.program, "lightMatrix");
+            let model_loc = gl.get_uniform_location(self.program, "model");
+            for (layer, matrix) in frame.matrices.iter().enumerate() {
+                gl.framebuffer_texture_layer(glow::FRAMEBUFFER, glow::DEPTH_ATTACHMENT, Some(self.texture), 0, layer as i32);
+                gl.clear(glow::DEPTH_BUFFER_BIT);
+                gl.uniform_matrix_4_f32_slice(light_matrix_loc.as_ref(), false, &matrix.to_cols_array());
+                scene.visit_visible(|node| {
+                    if let Some(mesh) = node.mesh {
+                        gl.uniform_matrix_4_f32_slice(model_loc.as_ref(), false, &node.world.to_cols_array());
+                        models[mesh].draw_depth(gl);
+                    }
+                });
+            }
+
+            gl.bind_framebuffer(glow::FRAMEBUFFER, previous_framebuffer);
+        }
+        frame
+    }
+}
+
+fn cascade_splits(near: f32, far: f32) -> [f32; CASCADES] {
+    std::array::from_fn(|cascade| {
+        let t = (cascade + 1) as f32 / CASCADES as f32;
+        let logarithmic = near * (far / near).powf(t);
+        let even = near + (far - near) * t;
+        CASCADE_LAMBDA * logarithmic + (1.0 - CASCADE_LAMBDA) * even
+    })
+}
+
+// Looking straight up or down the usual up vector is degenerate
+fn up_vector(direction: Vec3) -> Vec3 {
+    if direction.cross(Vec3::Y).length_squared() < 1e-6 { Vec3::Z } else { Vec3::Y }
+}
+
+// Orthographic projection around the bounding sphere of the camera frustum
+// between near and far. The sphere keeps the size constant as the camera
+// turns, which together with snapping to whole texels keeps the shadow edges
+// from shimmering.
+fn cascade_matrix(camera: &Camera, aspect: f32, near: f32, far: f32, direction: Vec3, bounds: Option<(Vec3, Vec3)>) -> Mat4 {
+    let position = camera.position();
+    let forward = -camera.direction();
+    let right = forward.cross(Vec3::Y).normalize();
+    let up = right.cross(forward);
+    let tan_half_fov = (camera.fov.to_radians() * 0.5).tan();
+
+    let mut corners = Vec::with_capacity(8);
+    for distance in [near, far] {
+        let (half_height, center) = (distance * tan_half_fov, position + forward * distance);
+        let half_width = half_height * aspect;
+        for (x, y) in [(1.0, 1.0), (-1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)] {
+            corners.push(center + right * (x * half_width) + up * (y * half_height));
+        }
+    }
+    let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+    let radius = corners.iter().map(|corner| corner.distance(center)).fold(0.0, f32::max);
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let view = Mat4::look_at_rh(center - direction * radius, center, up_vector(direction));
+    // Models between the light and the slice still cast shadows into it
+    let mut near_plane = 0.0f32;
+    if let Some((min, max)) = bounds {
+        for corner in utils::box_corners(min, max) {
+            near_plane = near_plane.min(-view.transform_point3(corner).z);
+        }
+    }
+    let projection = Mat4::orthographic_rh_gl(-radius, radius, -radius, radius, near_plane, 2.0 * radius);
+
+    let matrix = projection * view;
+    let texels_per_unit = SHADOW_MAP_SIZE as f32 / 2.0;
+    let origin = matrix.transform_point3(Vec3::ZERO) * texels_per_unit;
+    let offset = (origin.round() - origin) / texels_per_unit;
+    Mat4::from_translation(Vec3::new(offset.x, offset.y, 0.0)) * matrix
+}
+
+fn spot_matrix(position: Vec3, direction: Vec3, outer_angle: f32, range: f32) -> Mat4 {
+    let fov = (2.0 * outer_angle).clamp(1.0, 170.0).to_radians();
+    let view = Mat4::look_at_rh(position, position + direction, up_vector(direction));
+    Mat4::perspective_rh_gl(fov, 1.0, SPOT_NEAR_PLANE, range) * view
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cascades_cover_the_frustum_slices() {
+        let camera = Camera { yaw: 30.0, pitch: -20.0, ..Default::default() };
+        let splits = cascade_splits(0.1, SHADOW_DISTANCE);
+        assert!(splits.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!((splits[CASCADES - 1] - SHADOW_DISTANCE).abs() < 1e-3);
+
+        let direction = Vec3::new(-0.5, -1.0, -0.3).normalize();
+        let matrix = cascade_matrix(&camera, 16.0 / 9.0, 0.1, splits[0], direction, None);
+        // The point the camera looks at a little way in front lands in the map
+        let inside = camera.position() - camera.direction() * splits[0] * 0.5;
+        let clip = matrix.project_point3(inside);
+        assert!(clip.abs().max_element() <= 1.0, "{clip}");
+    }
+}
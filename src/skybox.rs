@@ -0,0 +1,81 @@
+use glam::{Mat3, Mat4};
+use glow::HasContext;
+use crate::error::RendererError;
+use crate::ibl::{Cube, Ibl};
+use crate::scene::{Environment, Sky};
+use crate::utils;
+
+const SKYBOX_VERTEX_SHADER: &str = "shaders/skyboxvertexshader.glsl";
+const SKYBOX_FRAGMENT_SHADER: &str = "shaders/skyboxfragmentshader.glsl";
+
+const MAP_SKY: i32 = 0;
+const GRADIENT_SKY: i32 = 1;
+
+// Draws the sky behind the models, at the far plane where nothing was drawn
+pub struct Skybox {
+    program: glow::Program,
+    cube: Cube,
+}
+
+// Turns world space directions into directions in the environment map
+pub fn environment_rotation(environment: &Environment) -> Mat3 {
+    Mat3::from_rotation_y(-environment.rotation.to_radians())
+}
+
+impl Skybox {
+    pub fn new(gl: &glow::Context) -> Result<Self, RendererError> {
+        let program = utils::load_program(gl, SKYBOX_VERTEX_SHADER, SKYBOX_FRAGMENT_SHADER)?;
+        let cube = Cube::new(gl)?;
+        Ok(Self { program, cube })
+    }
+
+    // Expects the depth buffer of the frame to be filled in. Map skies need
+    // the maps of the environment, without them nothing is drawn.
+    pub fn draw(&self, gl: &glow::Context, view: Mat4, projection: Mat4, environment: &Environment, ibl: Option<&Ibl>) {
+        let sky_type = match (environment.sky, ibl) {
+            (Some(Sky::Map), Some(_)) => MAP_SKY,
+            (Some(Sky::Gradient { .. }), _) => GRADIENT_SKY,
+            _ => return,
+        };
+
+        unsafe {
+            gl.use_program(Some(self.program));
+            // Only the rotation of the camera, the sky is infinitely far away
+            let view = Mat4::from_mat3(Mat3::from_mat4(view));
+            let view_loc = gl.get_uniform_location(self.program, "view");
+            gl.uniform_matrix_4_f32_slice(view_loc.as_ref(), false, &view.to_cols_array());
+            let projection_loc = gl.get_uniform_location(self.program, "projection");
+            gl.uniform_matrix_4_f32_slice(projection_loc.as_ref(), false, &projection.to_cols_array());
+            let rotation_loc = gl.get_uniform_location(self.program, "environmentRotation");
+            gl.uniform_matrix_3_f32_slice(rotation_loc.as_ref(), false, &environment_rotation(environment).to_cols_array());
+            let exposure_loc = gl.get_uniform_location(self.program, "exposure");
+            gl.uniform_1_f32(exposure_loc.as_ref(), environment.sky_exposure.exp2());
+            let type_loc = gl.get_uniform_location(self.program, "skyType");
+            gl.uniform_1_i32(type_loc.as_ref(), sky_type);
+
+            if let (Some(Sky::Map), Some(ibl)) = (environment.sky, ibl) {
+                gl.active_texture(glow::TEXTURE0);
+                gl.bind_texture(glow::TEXTURE_CUBE_MAP, Some(ibl.environment));
+            }
+            if let Some(Sky::Gradient { zenith, horizon, ground }) = environment.sky {
+                for (name, color) in [("zenithColor", zenith), ("horizonColor", horizon), ("groundColor", ground)] {
+                    let location = gl.get_uniform_location(self.program, name);
+                    gl.uniform_3_f32_slice(location.as_ref(), &color);
+                }
+            }
+
+            // The cube is drawn at depth 1, which the cleared depth still passes
+            gl.depth_func(glow::LEQUAL);
+            gl.bind_vertex_array(Some(self.cube.vao));
+            gl.draw_arrays(glow::TRIANGLES, 0, 36);
+            gl.depth_func(glow::LESS);
+        }
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_program(self.program);
+        }
+        self.cube.delete(gl);
+    }
+}
@@ -0,0 +1,291 @@
+use glam::{Mat4, Vec2, Vec3};
+use glow::HasContext;
+use crate::error::RendererError;
+use crate::ibl::FULLSCREEN_VERTEX_SHADER;
+use crate::model::Model;
+use crate::scene::Scene;
+use crate::utils;
+
+const NORMAL_VERTEX_SHADER: &str = "shaders/normalvertexshader.glsl";
+const NORMAL_FRAGMENT_SHADER: &str = "shaders/normalfragmentshader.glsl";
+const SSAO_FRAGMENT_SHADER: &str = "shaders/ssaofragmentshader.glsl";
+const SSAO_BLUR_FRAGMENT_SHADER: &str = "shaders/ssaoblurfragmentshader.glsl";
+
+// Has to match KERNEL_SIZE in ssaofragmentshader.glsl
+const KERNEL_SIZE: usize = 16;
+// The noise tile is this many pixels wide and high, and the blur as wide as
+// the tile so it averages the pattern away
+const NOISE_SIZE: usize = 4;
+
+// Textures of one frame size
+struct Targets {
+    size: (u32, u32),
+    prepass_framebuffer: glow::Framebuffer,
+    depth: glow::Texture,
+    // View space normals
+    normals: glow::Texture,
+    occlusion_framebuffer: glow::Framebuffer,
+    occlusion: glow::Texture,
+    blur_framebuffer: glow::Framebuffer,
+    blurred: glow::Texture,
+}
+
+impl Targets {
+    fn delete(&self, gl: &glow::Context) {
+        unsafe {
+            for framebuffer in [self.prepass_framebuffer, self.occlusion_framebuffer, self.blur_framebuffer] {
+                gl.delete_framebuffer(framebuffer);
+            }
+            for texture in [self.depth, self.normals, self.occlusion, self.blurred] {
+                gl.delete_texture(texture);
+            }
+        }
+    }
+}
+
+// Screen space ambient occlusion. The visible models are drawn once more for
+// their depth and normals, then each pixel checks how many points of a
+// hemisphere around it are hidden behind other geometry.
+pub struct Ssao {
+    prepass_program: glow::Program,
+    occlusion_program: glow::Program,
+    blur_program: glow::Program,
+    vao: glow::VertexArray,
+    // Turns the hemisphere differently for neighbouring pixels
+    noise: glow::Texture,
+    targets: Option<Targets>,
+}
+
+impl Ssao {
+    pub fn new(gl: &glow::Context) -> Result<Self, RendererError> {
+        let prepass_program = utils::load_program(gl, NORMAL_VERTEX_SHADER, NORMAL_FRAGMENT_SHADER)?;
+        let occlusion_program = utils::load_program(gl, FULLSCREEN_VERTEX_SHADER, SSAO_FRAGMENT_SHADER)?;
+        let blur_program = utils::load_program(gl, FULLSCREEN_VERTEX_SHADER, SSAO_BLUR_FRAGMENT_SHADER)?;
+
+        unsafe {
+            gl.use_program(Some(occlusion_program));
+            for (unit, name) in ["depthMap", "normalMap", "noiseMap"].iter().enumerate() {
+                let location = gl.get_uniform_location(occlusion_program, name);
+                gl.uniform_1_i32(location.as_ref(), unit as i32);
+            }
+            let kernel: Vec<f32> = hemisphere_kernel().iter().flat_map(|sample| sample.to_array()).collect();
+            let samples_loc = gl.get_uniform_location(occlusion_program, "samples");
+            gl.uniform_3_f32_slice(samples_loc.as_ref(), &kernel);
+
+            gl.use_program(Some(blur_program));
+            for (unit, name) in ["occlusionImage", "depthMap"].iter().enumerate() {
+                let location = gl.get_uniform_location(blur_program, name);
+                gl.uniform_1_i32(location.as_ref(), unit as i32);
+            }
+
+            let rotations: Vec<f32> = (0..NOISE_SIZE * NOISE_SIZE)
+                .flat_map(|i| {
+                    let angle = std::f32::consts::TAU * halton(i + 1, 7);
+                    [angle.cos(), angle.sin()]
+                })
+                .collect();
+            let noise = gl.create_texture().map_err(RendererError::Gl)?;
+            gl.bind_texture(glow::TEXTURE_2D, Some(noise));
+            gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                glow::RG16F as i32,
+                NOISE_SIZE as i32,
+                NOISE_SIZE as i32,
+                0,
+                glow::RG,
+                glow::FLOAT,
+                glow::PixelUnpackData::Slice(Some(bytemuck::cast_slice(&rotations))),
+            );
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, glow::NEAREST as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, glow::NEAREST as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::REPEAT as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::REPEAT as i32);
+
+            let vao = gl.create_vertex_array().map_err(RendererError::Gl)?;
+            Ok(Self { prepass_program, occlusion_program, blur_program, vao, noise, targets: None })
+        }
+    }
+
+    // Renders the occlusion of the scene's visible models as seen through
+    // view and projection into a texture of size, 1 where nothing occludes.
+    // Leaves the framebuffer that was bound before.
+    pub fn render(&mut self, gl: &glow::Context, size: (u32, u32), scene: &Scene, models: &[Model], view: Mat4, projection: Mat4) -> Result<(), RendererError> {
+        if self.targets.as_ref().is_none_or(|targets| targets.size != size) {
+            if let Some(targets) = self.targets.take() {
+                targets.delete(gl);
+            }
+            self.targets = Some(create_targets(gl, size)?);
+        }
+        let targets = self.targets.as_ref().expect("created above");
+        let settings = &scene.ambient_occlusion;
+
+        unsafe {
+            let previous_framebuffer = gl.get_parameter_framebuffer(glow::DRAW_FRAMEBUFFER_BINDING);
+            gl.viewport(0, 0, size.0 as i32, size.1 as i32);
+
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(targets.prepass_framebuffer));
+            gl.enable(glow::DEPTH_TEST);
+            gl.clear_color(0.0, 0.0, 0.0, 0.0);
+            gl.clear(glow::COLOR_BUFFER_BIT | glow::DEPTH_BUFFER_BIT);
+            gl.use_program(Some(self.prepass_program));
+            let view_loc = gl.get_uniform_location(self.prepass_program, "view");
+            gl.uniform_matrix_4_f32_slice(view_loc.as_ref(), false, &view.to_cols_array());
+            let projection_loc = gl.get_uniform_location(self.prepass_program, "projection");
+            gl.uniform_matrix_4_f32_slice(projection_loc.as_ref(), false, &projection.to_cols_array());
+            let model_loc = gl.get_uniform_location(self.prepass_program, "model");
+            scene.visit_visible(|node| {
+                if let Some(mesh) = node.mesh {
+                    gl.uniform_matrix_4_f32_slice(model_loc.as_ref(), false, &node.world.to_cols_array());
+                    models[mesh].draw_depth(gl);
+                }
+            });
+
+            gl.disable(glow::DEPTH_TEST);
+            gl.bind_vertex_array(Some(self.vao));
+            let inverse_projection = projection.inverse();
+
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(targets.occlusion_framebuffer));
+            gl.use_program(Some(self.occlusion_program));
+            let projection_loc = gl.get_uniform_location(self.occlusion_program, "projection");
+            gl.uniform_matrix_4_f32_slice(projection_loc.as_ref(), false, &projection.to_cols_array());
+            let inverse_loc = gl.get_uniform_location(self.occlusion_program, "inverseProjection");
+            gl.uniform_matrix_4_f32_slice(inverse_loc.as_ref(), false, &inverse_projection.to_cols_array());
+            let radius_loc = gl.get_uniform_location(self.occlusion_program, "radius");
+            gl.uniform_1_f32(radius_loc.as_ref(), settings.radius);
+            let intensity_loc = gl.get_uniform_location(self.occlusion_program, "intensity");
+            gl.uniform_1_f32(intensity_loc.as_ref(), settings.intensity);
+            let noise_scale = Vec2::new(size.0 as f32, size.1 as f32) / NOISE_SIZE as f32;
+            let noise_scale_loc = gl.get_uniform_location(self.occlusion_program, "noiseScale");
+            gl.uniform_2_f32(noise_scale_loc.as_ref(), noise_scale.x, noise_scale.y);
+            for (unit, texture) in [targets.depth, targets.normals, self.noise].into_iter().enumerate() {
+                gl.active_texture(glow::TEXTURE0 + unit as u32);
+                gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            }
+            gl.draw_arrays(glow::TRIANGLES, 0, 3);
+
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(targets.blur_framebuffer));
+            gl.use_program(Some(self.blur_program));
+            let inverse_loc = gl.get_uniform_location(self.blur_program, "inverseProjection");
+            gl.uniform_matrix_4_f32_slice(inverse_loc.as_ref(), false, &inverse_projection.to_cols_array());
+            let texel_loc = gl.get_uniform_location(self.blur_program, "texelSize");
+            gl.uniform_2_f32(texel_loc.as_ref(), 1.0 / size.0 as f32, 1.0 / size.1 as f32);
+            for (unit, texture) in [targets.occlusion, targets.depth].into_iter().enumerate() {
+                gl.active_texture(glow::TEXTURE0 + unit as u32);
+                gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            }
+            gl.draw_arrays(glow::TRIANGLES, 0, 3);
+
+            gl.enable(glow::DEPTH_TEST);
+            gl.bind_framebuffer(glow::FRAMEBUFFER, previous_framebuffer);
+        }
+        Ok(())
+    }
+
+    // The blurred occlusion of the last render()
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe {
+            for program in [self.prepass_program, self.occlusion_program, self.blur_program] {
+                gl.delete_program(program);
+            }
+            gl.delete_vertex_array(self.vao);
+            gl.delete_texture(self.noise);
+        }
+        if let Some(targets) = &self.targets {
+            targets.delete(gl);
+        }
+    }
+
+    pub fn bind_texture(&self, gl: &glow::Context, unit: u32) {
+        unsafe {
+            gl.active_texture(glow::TEXTURE0 + unit);
+            gl.bind_texture(glow::TEXTURE_2D, self.targets.as_ref().map(|targets| targets.blurred));
+        }
+    }
+}
+
+fn create_targets(gl: &glow::Context, size: (u32, u32)) -> Result<Targets, RendererError> {
+    unsafe {
+        let depth = create_texture(gl, size, glow::DEPTH_COMPONENT24)?;
+        let normals = create_texture(gl, size, glow::RGBA16F)?;
+        let occlusion = create_texture(gl, size, glow::R8)?;
+        let blurred = create_texture(gl, size, glow::R8)?;
+        let prepass_framebuffer = gl.create_framebuffer().map_err(RendererError::Gl)?;
+        let occlusion_framebuffer = gl.create_framebuffer().map_err(RendererError::Gl)?;
+        let blur_framebuffer = gl.create_framebuffer().map_err(RendererError::Gl)?;
+        let targets = Targets { size, prepass_framebuffer, depth, normals, occlusion_framebuffer, occlusion, blur_framebuffer, blurred };
+
+        let previous_framebuffer = gl.get_parameter_framebuffer(glow::DRAW_FRAMEBUFFER_BINDING);
+        let attachments = [
+            (prepass_framebuffer, vec![(glow::DEPTH_ATTACHMENT, depth), (glow::COLOR_ATTACHMENT0, normals)]),
+            (occlusion_framebuffer, vec![(glow::COLOR_ATTACHMENT0, occlusion)]),
+            (blur_framebuffer, vec![(glow::COLOR_ATTACHMENT0, blurred)]),
+        ];
+        let mut status = glow::FRAMEBUFFER_COMPLETE;
+        for (framebuffer, textures) in attachments {
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer));
+            for (attachment, texture) in textures {
+                gl.framebuffer_texture_2d(glow::FRAMEBUFFER, attachment, glow::TEXTURE_2D, Some(texture), 0);
+            }
+            if status == glow::FRAMEBUFFER_COMPLETE {
+                status = gl.check_framebuffer_status(glow::FRAMEBUFFER);
+            }
+        }
+        gl.bind_framebuffer(glow::FRAMEBUFFER, previous_framebuffer);
+
+        if status != glow::FRAMEBUFFER_COMPLETE {
+            targets.delete(gl);
+            return Err(RendererError::Gl(format!("SSAO framebuffer of {}x{} is incomplete (status {:#x})", size.0, size.1, status)));
+        }
+        Ok(targets)
+    }
+}
+
+// Read texel by texel, so without filtering
+unsafe fn create_texture(gl: &glow::Context, size: (u32, u32), format: u32) -> Result<glow::Texture, RendererError> {
+    let texture = gl.create_texture().map_err(RendererError::Gl)?;
+    gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+    gl.tex_storage_2d(glow::TEXTURE_2D, 1, format, size.0 as i32, size.1 as i32);
+    gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, glow::NEAREST as i32);
+    gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, glow::NEAREST as i32);
+    gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE as i32);
+    gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
+    Ok(texture)
+}
+
+// Points in the unit hemisphere around +Z, more of them close to the center
+// where occluders matter most. Halton points keep them evenly spread and the
+// same on every run.
+fn hemisphere_kernel() -> [Vec3; KERNEL_SIZE] {
+    std::array::from_fn(|i| {
+        let phi = std::f32::consts::TAU * halton(i + 1, 2);
+        let cos_theta = 1.0 - halton(i + 1, 3);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let direction = Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta);
+        let t = i as f32 / KERNEL_SIZE as f32;
+        direction * halton(i + 1, 5).max(0.1) * (0.1 + 0.9 * t * t)
+    })
+}
+
+fn halton(mut index: usize, base: usize) -> f32 {
+    let (mut result, mut fraction) = (0.0, 1.0);
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_stays_in_the_hemisphere() {
+        for sample in hemisphere_kernel() {
+            assert!(sample.z >= 0.0 && sample.length() <= 1.0, "{}", sample);
+            assert!(sample.length() > 0.0);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use glow::HasContext;
+use crate::error::RendererError;
+use crate::ibl::FULLSCREEN_VERTEX_SHADER;
+use crate::scene::{TonemapOperator, Tonemapping};
+use crate::utils;
+
+const TONEMAP_FRAGMENT_SHADER: &str = "shaders/tonemapfragmentshader.glsl";
+
+// Have to match the defines in tonemapfragmentshader.glsl
+const REINHARD: i32 = 0;
+const ACES: i32 = 1;
+const AGX: i32 = 2;
+
+// Floating point color and depth the scene is lit into before tonemapping.
+// Multisampled targets render into renderbuffers and resolve() copies the
+// color into the texture the tonemap pass reads.
+pub struct HdrTarget {
+    pub size: (u32, u32),
+    framebuffer: glow::Framebuffer,
+    depth: glow::Renderbuffer,
+    // Only when multisampled
+    multisampled_color: Option<glow::Renderbuffer>,
+    resolve_framebuffer: Option<glow::Framebuffer>,
+    texture: glow::Texture,
+}
+
+impl HdrTarget {
+    // samples of 0 or 1 are both no multisampling, counts past what the
+    // driver supports are lowered to its maximum
+    pub fn new(gl: &glow::Context, size: (u32, u32), samples: u8) -> Result<Self, RendererError> {
+        let (width, height) = (size.0 as i32, size.1 as i32);
+        unsafe {
+            let previous_framebuffer = gl.get_parameter_framebuffer(glow::DRAW_FRAMEBUFFER_BINDING);
+            let max_samples = gl.get_parameter_i32(glow::MAX_SAMPLES).clamp(1, u8::MAX as i32) as u8;
+            let samples = samples.min(max_samples);
+
+            let texture = gl.create_texture().map_err(RendererError::Gl)?;
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            gl.tex_storage_2d(glow::TEXTURE_2D, 1, glow::RGBA16F, width, height);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, glow::LINEAR as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, glow::LINEAR as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
+
+            let framebuffer = gl.create_framebuffer().map_err(RendererError::Gl)?;
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer));
+            let depth = gl.create_renderbuffer().map_err(RendererError::Gl)?;
+            gl.bind_renderbuffer(glow::RENDERBUFFER, Some(depth));
+
+            let mut target = Self {
+                size,
+                framebuffer,
+                depth,
+                multisampled_color: None,
+                resolve_framebuffer: None,
+                texture,
+            };
+
+            let result = if samples > 1 {
+                gl.renderbuffer_storage_multisample(glow::RENDERBUFFER, samples as i32, glow::DEPTH24_STENCIL8, width, height);
+                gl.framebuffer_renderbuffer(glow::FRAMEBUFFER, glow::DEPTH_STENCIL_ATTACHMENT, glow::RENDERBUFFER, Some(depth));
+                let color = gl.create_renderbuffer().map_err(RendererError::Gl)?;
+                target.multisampled_color = Some(color);
+                gl.bind_renderbuffer(glow::RENDERBUFFER, Some(color));
+                gl.renderbuffer_storage_multisample(glow::RENDERBUFFER, samples as i32, glow::RGBA16F, width, height);
+                gl.framebuffer_renderbuffer(glow::FRAMEBUFFER, glow::COLOR_ATTACHMENT0, glow::RENDERBUFFER, Some(color));
+                check_framebuffer(gl, size).and_then(|()| {
+                    let resolve = gl.create_framebuffer().map_err(RendererError::Gl)?;
+                    target.resolve_framebuffer = Some(resolve);
+                    gl.bind_framebuffer(glow::FRAMEBUFFER, Some(resolve));
+                    gl.framebuffer_texture_2d(glow::FRAMEBUFFER, glow::COLOR_ATTACHMENT0, glow::TEXTURE_2D, Some(texture), 0);
+                    check_framebuffer(gl, size)
+                })
+            } else {
+                gl.renderbuffer_storage(glow::RENDERBUFFER, glow::DEPTH24_STENCIL8, width, height);
+                gl.framebuffer_renderbuffer(glow::FRAMEBUFFER, glow::DEPTH_STENCIL_ATTACHMENT, glow::RENDERBUFFER, Some(depth));
+                gl.framebuffer_texture_2d(glow::FRAMEBUFFER, glow::COLOR_ATTACHMENT0, glow::TEXTURE_2D, Some(texture), 0);
+                check_framebuffer(gl, size)
+            };
+
+            gl.bind_framebuffer(glow::FRAMEBUFFER, previous_framebuffer);
+            if let Err(e) = result {
+                target.delete(gl);
+                return Err(e);
+            }
+            Ok(target)
+        }
+    }
+
+    pub fn bind(&self, gl: &glow::Context) {
+        unsafe {
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.framebuffer));
+        }
+    }
+
+    // Makes what was drawn available to texture(), leaves the read and draw
+    // framebuffers unbound
+    pub fn resolve(&self, gl: &glow::Context) {
+        let Some(resolve_framebuffer) = self.resolve_framebuffer else {
+            return;
+        };
+        let (width, height) = (self.size.0 as i32, self.size.1 as i32);
+        unsafe {
+            gl.bind_framebuffer(glow::READ_FRAMEBUFFER, Some(self.framebuffer));
+            gl.bind_framebuffer(glow::DRAW_FRAMEBUFFER, Some(resolve_framebuffer));
+            gl.blit_framebuffer(0, 0, width, height, 0, 0, width, height, glow::COLOR_BUFFER_BIT, glow::NEAREST);
+            gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+        }
+    }
+
+    pub fn texture(&self) -> glow::Texture {
+        self.texture
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_framebuffer(self.framebuffer);
+            gl.delete_renderbuffer(self.depth);
+            if let Some(color) = self.multisampled_color {
+                gl.delete_renderbuffer(color);
+            }
+            if let Some(resolve_framebuffer) = self.resolve_framebuffer {
+                gl.delete_framebuffer(resolve_framebuffer);
+            }
+            gl.delete_texture(self.texture);
+        }
+    }
+}
+
+unsafe fn check_framebuffer(gl: &glow::Context, size: (u32, u32)) -> Result<(), RendererError> {
+    let status = gl.check_framebuffer_status(glow::FRAMEBUFFER);
+    if status != glow::FRAMEBUFFER_COMPLETE {
+        return Err(RendererError::Gl(format!("HDR framebuffer of {}x{} is incomplete (status {:#x})", size.0, size.1, status)));
+    }
+    Ok(())
+}
+
+// Fullscreen pass turning the HDR frame into sRGB display colors
+pub struct TonemapPass {
+    program: glow::Program,
+    vao: glow::VertexArray,
+}
+
+impl TonemapPass {
+    pub fn new(gl: &glow::Context) -> Result<Self, RendererError> {
+        let program = utils::load_program(gl, FULLSCREEN_VERTEX_SHADER, TONEMAP_FRAGMENT_SHADER)?;
+        // The vertex shader makes its triangle from gl_VertexID
+        let vao = unsafe { gl.create_vertex_array().map_err(RendererError::Gl)? };
+        Ok(Self { program, vao })
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_program(self.program);
+            gl.delete_vertex_array(self.vao);
+        }
+    }
+
+    // Draws into the framebuffer that is bound, over its whole viewport
+    pub fn draw(&self, gl: &glow::Context, source: glow::Texture, tonemapping: &Tonemapping) {
+        let operator = match tonemapping.operator {
+            TonemapOperator::Reinhard => REINHARD,
+            TonemapOperator::Aces => ACES,
+            TonemapOperator::Agx => AGX,
+        };
+
+        unsafe {
+            gl.use_program(Some(self.program));
+            gl.active_texture(glow::TEXTURE0);
+            gl.bind_texture(glow::TEXTURE_2D, Some(source));
+            let operator_loc = gl.get_uniform_location(self.program, "tonemapOperator");
+            gl.uniform_1_i32(operator_loc.as_ref(), operator);
+            let exposure_loc = gl.get_uniform_location(self.program, "exposure");
+            gl.uniform_1_f32(exposure_loc.as_ref(), tonemapping.exposure.exp2());
+
+            gl.disable(glow::DEPTH_TEST);
+            gl.bind_vertex_array(Some(self.vao));
+            gl.draw_arrays(glow::TRIANGLES, 0, 3);
+            gl.enable(glow::DEPTH_TEST);
+        }
+    }
+}
@@ -1,8 +1,11 @@
-use glow::{HasContext, NativeProgram};
+use glow::HasContext;
+use glutin::prelude::*;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
-use rayon::prelude::*;
+use crate::error::{self, RendererError, ShaderStage};
+use crate::material::Material;
+use crate::model::{MeshData, Submesh};
 
 pub fn profile<F, T>(name: &str, f: F) -> T 
 where 
@@ -16,131 +19,23 @@ where
 }
 
 
-pub fn load_model_with_textures(gl: &glow::Context, shader_program: &NativeProgram ,path: &str) -> (glow::VertexArray, i32) {
-        let (vertices, indices)  = load_mesh(path);
-
-        let albedo_path = path.strip_suffix(".obj").unwrap().to_owned() + "Textures/AlbedoTransparency.png";
-		let ao_path = path.strip_suffix(".obj").unwrap().to_owned() + "Textures/AO.png";
-		let metallic_path = path.strip_suffix(".obj").unwrap().to_owned() + "Textures/MetallicSmoothness.png";
-		let normal_path = path.strip_suffix(".obj").unwrap().to_owned() + "Textures/Normal.png";
-        let texture_paths = vec![
-            albedo_path.as_str(),
-			ao_path.as_str(),
-			metallic_path.as_str(),
-			normal_path.as_str()
-        ];
-
-
-        let images: Vec<_> = profile("images", || {texture_paths
-            .par_iter()
-            .map(|path| get_image_data(path))
-            .collect()});
-
-        let textures: Vec<_> = images
-            .into_iter()
-            .map(|image| generate_texture(&gl, image).unwrap())
-            .collect();
-
-        unsafe {
-            let vao = gl.create_vertex_array().unwrap();
-            let vbo = gl.create_buffer().unwrap();
-            let ebo = gl.create_buffer().unwrap();
-            
-            gl.bind_vertex_array(Some(vao));
-
-            gl.bind_buffer(glow::ARRAY_BUFFER, Some(vbo));
-            gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                bytemuck::cast_slice(&vertices),
-                glow::STATIC_DRAW,
-            );
-
-            gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(ebo));
-            gl.buffer_data_u8_slice(
-                glow::ELEMENT_ARRAY_BUFFER,
-                bytemuck::cast_slice(&indices),
-                glow::STATIC_DRAW,
-            );
-
-            const POSITION_ATTRIB: u32 = 0;
-            const NORMAL_ATTRIB: u32 = 1;
-            const TEXCOORD_ATTRIB: u32 = 2;
-
-            const VERTEX_SIZE: usize = std::mem::size_of::<f32>();
-            const STRIDE: i32 = (8 * VERTEX_SIZE) as i32; // 3 pos + 3 normal + 2 uv = 8 floats
-
-            const POSITION_OFFSET: i32 = 0;
-            const NORMAL_OFFSET: i32 = (3 * VERTEX_SIZE) as i32;
-            const TEXCOORD_OFFSET: i32 = (6 * VERTEX_SIZE) as i32;
-
-            gl.vertex_attrib_pointer_f32(
-                POSITION_ATTRIB,
-                3,  // vec3
-                glow::FLOAT,
-                false,
-                STRIDE,
-                POSITION_OFFSET
-            );
-
-            gl.vertex_attrib_pointer_f32(
-                NORMAL_ATTRIB,
-                3,  // vec3
-                glow::FLOAT,
-                false,
-                STRIDE,
-                NORMAL_OFFSET
-            );
-
-            gl.vertex_attrib_pointer_f32(
-                TEXCOORD_ATTRIB,
-                2,  // vec2
-                glow::FLOAT,
-                false,
-                STRIDE,
-                TEXCOORD_OFFSET
-            );
-
-            // Don't forget to enable all attribute arrays
-            gl.enable_vertex_attrib_array(POSITION_ATTRIB);
-            gl.enable_vertex_attrib_array(NORMAL_ATTRIB);
-            gl.enable_vertex_attrib_array(TEXCOORD_ATTRIB);
-
-            gl.active_texture(glow::TEXTURE0);
-            gl.bind_texture(glow::TEXTURE_2D, Some(textures[0]));
-            let albedotexture_loc = gl.get_uniform_location(*shader_program, "albedoMap");
-            gl.uniform_1_i32(albedotexture_loc.as_ref(), 0);
-            
-            gl.active_texture(glow::TEXTURE1);
-            gl.bind_texture(glow::TEXTURE_2D, Some(textures[1]));
-            let ao_loc = gl.get_uniform_location(*shader_program, "aoMap");
-            gl.uniform_1_i32(ao_loc.as_ref(), 1);
-            
-            gl.active_texture(glow::TEXTURE2);
-            gl.bind_texture(glow::TEXTURE_2D, Some(textures[2]));
-            let metallic_loc = gl.get_uniform_location(*shader_program, "metallicMap");
-            gl.uniform_1_i32(metallic_loc.as_ref(), 2);
-            
-            gl.active_texture(glow::TEXTURE3);
-            gl.bind_texture(glow::TEXTURE_2D, Some(textures[3]));
-            let normal_loc = gl.get_uniform_location(*shader_program, "normalMap");
-            gl.uniform_1_i32(normal_loc.as_ref(), 3);
-			
-			(vao, indices.len() as i32)
-		}
-}
-
-pub fn get_image_data(path: &str) -> image::RgbaImage {
-		let image =  image::open(get_asset_path(path)).unwrap().flipv().into_rgba8();
-		image
+pub fn get_image_data(path: &str) -> Result<image::RgbaImage, RendererError> {
+    let path = get_asset_path(path);
+    match image::open(&path) {
+        Ok(image) => Ok(image.flipv().into_rgba8()),
+        Err(source) => Err(RendererError::Image { path, source }),
+    }
 }
 
-pub fn generate_texture(gl: &glow::Context, image: image::RgbaImage) -> Result<glow::Texture, Box<dyn std::error::Error>> {
+// Color maps are stored in sRGB and come out of the sampler linear, data maps
+// like normals are used as they are
+pub fn generate_texture(gl: &glow::Context, image: image::RgbaImage, srgb: bool) -> Result<glow::Texture, RendererError> {
     unsafe {
         // Load image
         let (width, height) = image.dimensions();
 
         // Create texture
-        let texture = gl.create_texture()?;
+        let texture = gl.create_texture().map_err(RendererError::Gl)?;
         gl.bind_texture(glow::TEXTURE_2D, Some(texture));
 
         // Set texture parameters
@@ -169,7 +64,7 @@ pub fn generate_texture(gl: &glow::Context, image: image::RgbaImage) -> Result<g
         gl.tex_image_2d(
             glow::TEXTURE_2D,
             0,
-            glow::RGBA as i32,
+            if srgb { glow::SRGB8_ALPHA8 } else { glow::RGBA8 } as i32,
             width as i32,
             height as i32,
             0,
@@ -185,6 +80,148 @@ pub fn generate_texture(gl: &glow::Context, image: image::RgbaImage) -> Result<g
     }
 }
 
+pub fn describe_config(display: &impl GlDisplay, config: &impl GlConfig) -> String {
+    format!(
+        "Using {} with config: {} samples, depth {}, stencil {}, alpha {}",
+        display.version_string(),
+        config.num_samples(),
+        config.depth_size(),
+        config.stencil_size(),
+        config.alpha_size(),
+    )
+}
+
+pub struct Framebuffer {
+    pub framebuffer: glow::Framebuffer,
+    pub size: (u32, u32),
+    color: glow::Renderbuffer,
+    depth: glow::Renderbuffer,
+}
+
+impl Framebuffer {
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_framebuffer(self.framebuffer);
+            gl.delete_renderbuffer(self.color);
+            gl.delete_renderbuffer(self.depth);
+        }
+    }
+}
+
+pub fn create_framebuffer(gl: &glow::Context, width: u32, height: u32) -> Result<Framebuffer, RendererError> {
+    unsafe {
+        let framebuffer = gl.create_framebuffer().map_err(RendererError::Gl)?;
+        gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer));
+
+        let color = gl.create_renderbuffer().map_err(RendererError::Gl)?;
+        gl.bind_renderbuffer(glow::RENDERBUFFER, Some(color));
+        gl.renderbuffer_storage(glow::RENDERBUFFER, glow::RGBA8, width as i32, height as i32);
+        gl.framebuffer_renderbuffer(glow::FRAMEBUFFER, glow::COLOR_ATTACHMENT0, glow::RENDERBUFFER, Some(color));
+
+        let depth = gl.create_renderbuffer().map_err(RendererError::Gl)?;
+        gl.bind_renderbuffer(glow::RENDERBUFFER, Some(depth));
+        gl.renderbuffer_storage(glow::RENDERBUFFER, glow::DEPTH24_STENCIL8, width as i32, height as i32);
+        gl.framebuffer_renderbuffer(glow::FRAMEBUFFER, glow::DEPTH_STENCIL_ATTACHMENT, glow::RENDERBUFFER, Some(depth));
+
+        let framebuffer = Framebuffer { framebuffer, size: (width, height), color, depth };
+        let status = gl.check_framebuffer_status(glow::FRAMEBUFFER);
+        if status != glow::FRAMEBUFFER_COMPLETE {
+            framebuffer.delete(gl);
+            return Err(RendererError::Gl(format!("Framebuffer of {}x{} is incomplete (status {:#x})", width, height, status)));
+        }
+        Ok(framebuffer)
+    }
+}
+
+pub fn read_pixels(gl: &glow::Context, size: (u32, u32)) -> image::RgbaImage {
+    let mut pixels = vec![0u8; (size.0 * size.1 * 4) as usize];
+    unsafe {
+        gl.pixel_store_i32(glow::PACK_ALIGNMENT, 1);
+        gl.read_pixels(
+            0,
+            0,
+            size.0 as i32,
+            size.1 as i32,
+            glow::RGBA,
+            glow::UNSIGNED_BYTE,
+            glow::PixelPackData::Slice(Some(&mut pixels)),
+        );
+    }
+    // OpenGL rows start at the bottom of the image
+    image::imageops::flip_vertical(&image::RgbaImage::from_raw(size.0, size.1, pixels).unwrap())
+}
+
+// Averages each factor x factor block of pixels into one. The averaging is
+// done in linear light, or edges against bright backgrounds come out too dark.
+pub fn downsample(image: &image::RgbaImage, factor: u32) -> image::RgbaImage {
+    if factor <= 1 {
+        return image.clone();
+    }
+
+    let to_linear: [f32; 256] = std::array::from_fn(|value| srgb_to_linear(value as f32 / 255.0));
+    let count = (factor * factor) as f32;
+    image::RgbaImage::from_fn(image.width() / factor, image.height() / factor, |x, y| {
+        let mut sum = [0.0f32; 4];
+        for block_y in 0..factor {
+            for block_x in 0..factor {
+                let pixel = image.get_pixel(x * factor + block_x, y * factor + block_y);
+                for channel in 0..3 {
+                    sum[channel] += to_linear[pixel[channel] as usize];
+                }
+                sum[3] += pixel[3] as f32 / 255.0;
+            }
+        }
+        let encode = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        image::Rgba([
+            encode(linear_to_srgb(sum[0] / count)),
+            encode(linear_to_srgb(sum[1] / count)),
+            encode(linear_to_srgb(sum[2] / count)),
+            encode(sum[3] / count),
+        ])
+    })
+}
+
+// EXR files hold linear light in floats, the frame read back is sRGB encoded
+fn to_linear_rgba32f(image: &image::RgbaImage) -> image::Rgba32FImage {
+    let to_linear: [f32; 256] = std::array::from_fn(|value| srgb_to_linear(value as f32 / 255.0));
+    image::Rgba32FImage::from_fn(image.width(), image.height(), |x, y| {
+        let [red, green, blue, alpha] = image.get_pixel(x, y).0;
+        image::Rgba([to_linear[red as usize], to_linear[green as usize], to_linear[blue as usize], alpha as f32 / 255.0])
+    })
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn save_image(image: &image::RgbaImage, path: &Path) -> Result<(), RendererError> {
+    let result = match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("exr") => to_linear_rgba32f(image).save(path),
+        _ => image.save(path),
+    };
+    result.map_err(|source| RendererError::Image { path: path.to_path_buf(), source })
+}
+
+pub fn box_corners(min: glam::Vec3, max: glam::Vec3) -> [glam::Vec3; 8] {
+    std::array::from_fn(|corner| glam::Vec3::new(
+        if corner & 1 == 0 { min.x } else { max.x },
+        if corner & 2 == 0 { min.y } else { max.y },
+        if corner & 4 == 0 { min.z } else { max.z },
+    ))
+}
+
 pub fn get_asset_path(relative_path: &str) -> PathBuf {
     let base_dir = if cfg!(debug_assertions) {
         PathBuf::from(env!("CARGO_MANIFEST_DIR"))
@@ -196,24 +233,41 @@ pub fn get_asset_path(relative_path: &str) -> PathBuf {
             .to_path_buf()
     };
     
-    return base_dir.join(relative_path)
+    base_dir.join(relative_path)
 }
 
-pub fn load_mesh(path: &str) -> (Vec<f32>, Vec<u32>) {
+pub fn load_mesh(path: &str) -> Result<MeshData, RendererError> {
     let obj_path = get_asset_path(path);
 
-    let (models, _) = tobj::load_obj(&obj_path, &tobj::LoadOptions {
+    let (models, materials) = tobj::load_obj(&obj_path, &tobj::LoadOptions {
         triangulate: true,
         single_index: true,
         ..Default::default()
     })
-    .expect("Failed to load OBJ file");
+    .map_err(|source| RendererError::Obj { path: obj_path.clone(), source })?;
+
+    // A missing .mtl is not fatal, the model just renders with the default material
+    let base_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let mut materials: Vec<Material> = match materials {
+        Ok(materials) => materials
+            .iter()
+            .map(|material| Material::from_mtl(material, base_dir))
+            .collect(),
+        Err(e) => {
+            eprintln!("Failed to load materials for {}: {}", path, e);
+            Vec::new()
+        }
+    };
+    let mut default_material = None;
 
     let mut vertices = Vec::new();
     let mut indices = Vec::new();
+    let mut submeshes = Vec::new();
 
     for model in models {
         let mesh = &model.mesh;
+        // Each tobj mesh indexes its own vertices from zero
+        let base_vertex = (vertices.len() / 8) as u32;
         
         // Store vertices
         for i in 0..mesh.positions.len() / 3 {
@@ -240,44 +294,103 @@ pub fn load_mesh(path: &str) -> (Vec<f32>, Vec<u32>) {
             }
         }
 
-        indices.extend_from_slice(&mesh.indices);
+        let material = match mesh.material_id {
+            Some(id) if id < materials.len() => id,
+            _ => *default_material.get_or_insert_with(|| {
+                materials.push(Material::default());
+                materials.len() - 1
+            }),
+        };
+
+        submeshes.push(Submesh {
+            index_offset: indices.len() as u32,
+            index_count: mesh.indices.len() as u32,
+            material,
+        });
+        indices.extend(mesh.indices.iter().map(|index| index + base_vertex));
     }
 
-    (vertices, indices)
+    Ok(MeshData { vertices, indices, submeshes, materials })
 }
 
-pub fn load_shader(shader_path: &str) -> String {
+pub fn load_shader(shader_path: &str) -> Result<String, RendererError> {
     let shader_path = get_asset_path(shader_path);
     fs::read_to_string(&shader_path)
-        .unwrap_or_else(|_| panic!("Failed to read shader file: {}", shader_path.display()))
+        .map_err(|source| RendererError::Io { path: shader_path, source })
 }
 
-pub fn compile_shader(gl: &glow::Context, source: &str, shader_type: u32) -> glow::Shader {
+// path is only used to report errors, the source has already been loaded
+pub fn compile_shader(gl: &glow::Context, path: &str, source: &str, stage: ShaderStage) -> Result<glow::Shader, RendererError> {
     unsafe {
-        let shader = gl.create_shader(shader_type).expect("Cannot create shader");
+        let shader = gl.create_shader(stage.gl_type()).map_err(RendererError::Gl)?;
         gl.shader_source(shader, source);
         gl.compile_shader(shader);
 
         if !gl.get_shader_compile_status(shader) {
-            panic!("Failed to compile shader: {}", gl.get_shader_info_log(shader));
+            let log = gl.get_shader_info_log(shader);
+            gl.delete_shader(shader);
+            return Err(RendererError::ShaderCompile {
+                path: get_asset_path(path),
+                stage,
+                excerpt: error::source_excerpt(source, &log),
+                log,
+            });
         }
-        shader
+        Ok(shader)
     }
 }
 
-pub fn create_shader_program(gl: &glow::Context, vertex_shader: glow::Shader, fragment_shader: glow::Shader) -> glow::Program {
+// Loads, compiles and links a vertex and fragment shader from the asset directory
+pub fn load_program(gl: &glow::Context, vertex_path: &str, fragment_path: &str) -> Result<glow::Program, RendererError> {
+    let vertex_source = load_shader(vertex_path)?;
+    let fragment_source = load_shader(fragment_path)?;
+    let vertex_shader = compile_shader(gl, vertex_path, &vertex_source, ShaderStage::Vertex)?;
+    let fragment_shader = compile_shader(gl, fragment_path, &fragment_source, ShaderStage::Fragment).inspect_err(|_| unsafe {
+        gl.delete_shader(vertex_shader);
+    })?;
+    create_shader_program(gl, vertex_shader, fragment_shader)
+}
+
+pub fn create_shader_program(gl: &glow::Context, vertex_shader: glow::Shader, fragment_shader: glow::Shader) -> Result<glow::Program, RendererError> {
     unsafe {
-        let program = gl.create_program().expect("Cannot create program");
+        let program = gl.create_program().map_err(RendererError::Gl)?;
         gl.attach_shader(program, vertex_shader);
         gl.attach_shader(program, fragment_shader);
         gl.link_program(program);
 
+        gl.delete_shader(vertex_shader);
+        gl.delete_shader(fragment_shader);
+
         if !gl.get_program_link_status(program) {
-            panic!("Failed to link program: {}", gl.get_program_info_log(program));
+            let log = gl.get_program_info_log(program);
+            gl.delete_program(program);
+            return Err(RendererError::ProgramLink { log });
         }
+        Ok(program)
+    }
+}
 
-        gl.delete_shader(vertex_shader);
-        gl.delete_shader(fragment_shader);
-        program
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downsample_averages_in_linear_light() {
+        let image = image::RgbaImage::from_fn(4, 2, |x, _| {
+            if x % 2 == 0 { image::Rgba([0, 0, 0, 255]) } else { image::Rgba([255, 255, 255, 255]) }
+        });
+        let downsampled = downsample(&image, 2);
+        assert_eq!(downsampled.dimensions(), (2, 1));
+        // Half of white in linear light, not 128
+        assert_eq!(downsampled.get_pixel(0, 0).0, [188, 188, 188, 255]);
+    }
+
+    #[test]
+    fn exr_pixels_are_linear() {
+        let image = image::RgbaImage::from_pixel(1, 1, image::Rgba([188, 188, 188, 255]));
+        let [red, green, blue, alpha] = to_linear_rgba32f(&image).get_pixel(0, 0).0;
+        // sRGB 188 is half of white in linear light
+        assert!((red - 0.5).abs() < 0.01, "{}", red);
+        assert_eq!((green, blue, alpha), (red, red, 1.0));
     }
-}
\ No newline at end of file
+}
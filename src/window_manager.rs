@@ -1,45 +1,251 @@
+use std::path::PathBuf;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use winit::application::ApplicationHandler;
-use winit::event:: WindowEvent;
+use winit::event::{DeviceEvent, DeviceId, ElementState, WindowEvent};
 use winit::event_loop::ActiveEventLoop;
-use winit::keyboard::Key;
+use winit::keyboard::{Key, PhysicalKey};
 use winit::platform::modifier_supplement::KeyEventExtModifierSupplement;
-use winit::window::{Window, WindowId};
+use winit::dpi::PhysicalSize;
+use winit::window::{CursorGrabMode, Fullscreen, Window, WindowId};
+use crate::bindings::{Action, Trigger};
+use crate::camera::{CameraController, FlyInput, OrbitCamera};
+use crate::cli::Options;
+use crate::input::InputState;
 use crate::render_manager::RenderManager;
+use crate::utils;
 
 pub struct App {
+    options: Options,
     window: Option<Window>,
     render_manager: Option<RenderManager>,
+    input: InputState,
+    pending_screenshot: Option<u32>,
+    camera: CameraController,
+    last_frame: Option<Instant>,
 }
 
-impl Default for App {
-    fn default() -> Self {
+const SCREENSHOT_SCALE: u32 = 4;
+// Camera yaw and pitch change per orbit key press, in degrees
+const CAMERA_STEP: f32 = 2.865;
+// Target movement per move scene key press, in world units
+const PAN_STEP: f32 = 0.1;
+// Exposure change per key press, in stops
+const EXPOSURE_STEP: f32 = 0.5;
+
+impl App {
+    pub fn new(options: Options) -> Self {
         Self {
             window: None,
             render_manager: None,
+            input: InputState::default(),
+            pending_screenshot: None,
+            camera: CameraController::Orbit(OrbitCamera::new(options.render.scene.camera)),
+            last_frame: None,
+            options,
+        }
+    }
+
+    fn save_scene(&self) {
+        let Some(render_manager) = &self.render_manager else {
+            return;
+        };
+
+        let path = self.options.save_path();
+        match render_manager.scene().save(path) {
+            Ok(()) => println!("Saved scene to {}", utils::get_asset_path(path).display()),
+            Err(e) => eprintln!("Failed to save scene: {}", e),
+        }
+    }
+
+    fn toggle_shading(&mut self) {
+        if let Some(render_manager) = &mut self.render_manager {
+            let shading = render_manager.shading().toggle();
+            render_manager.set_shading(shading);
+            println!("Shading: {}", shading);
+        }
+    }
+
+    // Changes the scene's tonemapping, so Ctrl+S saves it
+    fn adjust_tonemapping(&mut self, action: Action) {
+        let Some(render_manager) = &mut self.render_manager else {
+            return;
+        };
+
+        let tonemapping = &mut render_manager.scene_mut().tonemapping;
+        match action {
+            Action::CycleTonemap => tonemapping.operator = tonemapping.operator.next(),
+            Action::ExposureUp => tonemapping.exposure += EXPOSURE_STEP,
+            Action::ExposureDown => tonemapping.exposure -= EXPOSURE_STEP,
+            _ => return,
+        }
+        println!("Tonemapping: {:?}, exposure {:+.1}", tonemapping.operator, tonemapping.exposure);
+    }
+
+    // Flips every pass of the effect in the scene's post chain, so Ctrl+S
+    // saves it
+    fn toggle_post_effect(&mut self, action: Action) {
+        let Some(render_manager) = &mut self.render_manager else {
+            return;
+        };
+
+        let name = match action {
+            Action::ToggleBloom => "bloom",
+            Action::ToggleFxaa => "fxaa",
+            Action::ToggleChromaticAberration => "chromatic_aberration",
+            Action::ToggleVignette => "vignette",
+            Action::ToggleColorGrading => "color_grading",
+            _ => return,
+        };
+        let mut found = false;
+        for effect in render_manager.scene_mut().post.iter_mut().filter(|effect| effect.name() == name) {
+            effect.enabled = !effect.enabled;
+            found = true;
+            println!("Post effect {}: {}", name, if effect.enabled { "on" } else { "off" });
         }
+        if !found {
+            println!("The scene's post chain has no {} pass", name);
+        }
+    }
+
+    fn toggle_ambient_occlusion(&mut self) {
+        if let Some(render_manager) = &mut self.render_manager {
+            let ambient_occlusion = &mut render_manager.scene_mut().ambient_occlusion;
+            ambient_occlusion.enabled = !ambient_occlusion.enabled;
+            println!("Ambient occlusion: {}", if ambient_occlusion.enabled { "on" } else { "off" });
+        }
+    }
+
+    // The fly camera looks around with the mouse, so it keeps the cursor
+    fn toggle_camera(&mut self) {
+        self.camera.toggle();
+        let flying = matches!(self.camera, CameraController::Fly(_));
+        let Some(window) = &self.window else {
+            return;
+        };
+
+        if flying {
+            // Not every platform can lock the cursor in place
+            let grabbed = window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined));
+            if let Err(e) = grabbed {
+                eprintln!("Failed to grab cursor: {}", e);
+            }
+        } else if let Err(e) = window.set_cursor_grab(CursorGrabMode::None) {
+            eprintln!("Failed to release cursor: {}", e);
+        }
+        window.set_cursor_visible(!flying);
+    }
+
+    fn run(&mut self, action: Action, event_loop: &ActiveEventLoop) {
+        match action {
+            Action::SaveScene => self.save_scene(),
+            Action::ToggleCamera => self.toggle_camera(),
+            Action::ToggleShading => self.toggle_shading(),
+            Action::CycleTonemap | Action::ExposureUp | Action::ExposureDown => self.adjust_tonemapping(action),
+            Action::ToggleBloom | Action::ToggleFxaa | Action::ToggleChromaticAberration | Action::ToggleVignette
+                | Action::ToggleColorGrading => self.toggle_post_effect(action),
+            Action::ToggleAmbientOcclusion => self.toggle_ambient_occlusion(),
+            Action::Screenshot => self.pending_screenshot = Some(1),
+            Action::SupersampledScreenshot => self.pending_screenshot = Some(SCREENSHOT_SCALE),
+            Action::Exit => event_loop.exit(),
+            action => self.step_orbit(action),
+        }
+    }
+
+    // Actions that step the orbit camera once per press, the fly camera moves
+    // while its actions are held instead
+    fn step_orbit(&mut self, action: Action) {
+        let CameraController::Orbit(orbit) = &mut self.camera else {
+            return;
+        };
+
+        match action {
+            // Moves the scene like WASD used to move the models
+            Action::MoveSceneUp => orbit.pan(0.0, -PAN_STEP),
+            Action::MoveSceneDown => orbit.pan(0.0, PAN_STEP),
+            Action::MoveSceneLeft => orbit.pan(PAN_STEP, 0.0),
+            Action::MoveSceneRight => orbit.pan(-PAN_STEP, 0.0),
+            Action::FrameScene => {
+                if let Some(bounds) = self.render_manager.as_mut().and_then(|render_manager| render_manager.scene_bounds()) {
+                    orbit.frame(bounds.0, bounds.1);
+                }
+            },
+            Action::OrbitUp => orbit.orbit(0.0, CAMERA_STEP),
+            Action::OrbitDown => orbit.orbit(0.0, -CAMERA_STEP),
+            Action::OrbitLeft => orbit.orbit(-CAMERA_STEP, 0.0),
+            Action::OrbitRight => orbit.orbit(CAMERA_STEP, 0.0),
+            _ => (),
+        }
+    }
+
+    fn press(&mut self, triggers: &[Trigger], repeat: bool, event_loop: &ActiveEventLoop) {
+        for action in self.options.bindings.pressed(triggers, self.input.modifiers()) {
+            if !repeat || action.repeats() {
+                self.run(action, event_loop);
+            }
+        }
+    }
+
+    // Applies the input gathered since the last frame
+    fn update_camera(&mut self, dt: f32, viewport_height: f32) {
+        let (input, bindings) = (&self.input, &self.options.bindings);
+        match &mut self.camera {
+            CameraController::Orbit(orbit) => {
+                let delta = input.cursor_delta();
+                if bindings.held(Action::OrbitDrag, input) {
+                    orbit.drag(delta.x, delta.y);
+                } else if bindings.held(Action::PanDrag, input) {
+                    orbit.drag_pan(delta.x, delta.y, viewport_height);
+                }
+                orbit.zoom(input.scroll());
+                orbit.update(dt);
+            }
+            CameraController::Fly(fly) => {
+                let motion = input.mouse_motion();
+                fly.look(motion.x, motion.y);
+                fly.update(dt, FlyInput {
+                    forward: bindings.axis(Action::FlyForward, Action::FlyBack, input),
+                    right: bindings.axis(Action::FlyRight, Action::FlyLeft, input),
+                    up: bindings.axis(Action::FlyUp, Action::FlyDown, input),
+                    fast: bindings.held(Action::FlyFast, input),
+                });
+            }
+        }
+        self.input.end_frame();
     }
 }
 
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let window_attributes = Window::default_attributes()
+        let mut window_attributes = Window::default_attributes()
             .with_title("3D Window")
             .with_visible(false);
+        if let Some((width, height)) = self.options.window_size {
+            window_attributes = window_attributes.with_inner_size(PhysicalSize::new(width, height));
+        }
+        if self.options.fullscreen {
+            window_attributes = window_attributes.with_fullscreen(Some(Fullscreen::Borderless(None)));
+        }
         let window = event_loop.create_window(window_attributes).unwrap();
-        self.render_manager = Some(RenderManager::new(&window));
+        match RenderManager::new(&window, &self.options.render) {
+            Ok(render_manager) => self.render_manager = Some(render_manager),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                event_loop.exit();
+                return;
+            }
+        }
         window.set_visible(true);
-        window.set_maximized(true);
+        if self.options.window_size.is_none() && !self.options.fullscreen {
+            window.set_maximized(true);
+        }
         self.window = Some(window);
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
-        static mut ZOOM: f64 = 1.0;
-        static mut POS: (f64, f64) = (0.0, 0.0);
-        static mut DIFF: (f64, f64) =  (0.0, 0.0);
-        static mut PRESSED: bool =  false;
-        static mut MODEL: (f32, f32) = (0.0, 0.0);
-        static mut CAMERA: (f32, f32) = (0.0, 0.0);
-        
+        self.input.handle_window_event(&event);
+
         let size = {
             let window = self.window.as_ref().unwrap();
             let size = window.inner_size();
@@ -50,60 +256,74 @@ impl ApplicationHandler for App {
             WindowEvent::CloseRequested => {
                 event_loop.exit();
             },
-            WindowEvent::RedrawRequested => {
+            WindowEvent::Resized(new_size) => {
                 if let Some(render_manager) = &self.render_manager {
-                    
-                    render_manager.render(size, unsafe {DIFF}, unsafe {ZOOM}, unsafe {MODEL}, unsafe {CAMERA});
-                    self.window.as_ref().unwrap().request_redraw();
+                    render_manager.resize((new_size.width, new_size.height));
                 }
             }
-            WindowEvent::MouseWheel { device_id:_, delta, phase:_} => {
-                unsafe {
-                    ZOOM += 0.05 * match delta {
-                        winit::event::MouseScrollDelta::LineDelta(_, y) => y as f64,
-                        winit::event::MouseScrollDelta::PixelDelta(pos) => pos.y,
-                    };
-                }
-            },
-            WindowEvent::CursorMoved { device_id: _, position } => {
-                unsafe {
-                    if PRESSED {
-                        DIFF = (DIFF.0 + position.x - POS.0, DIFF.1 + position.y - POS.1);
+            WindowEvent::RedrawRequested => {
+                let now = Instant::now();
+                let dt = self.last_frame.map_or(0.0, |last_frame| (now - last_frame).as_secs_f32());
+                self.last_frame = Some(now);
+                self.update_camera(dt, size.1 as f32);
+
+                if let Some(render_manager) = &mut self.render_manager {
+                    render_manager.scene_mut().camera = self.camera.camera();
+
+                    if let Some(scale) = self.pending_screenshot.take() {
+                        let path = screenshot_path();
+                        let saved = render_manager
+                            .capture(size, scale)
+                            .and_then(|image| utils::save_image(&image, &path));
+                        match saved {
+                            Ok(()) => println!("Saved screenshot to {}", path.display()),
+                            Err(e) => eprintln!("Failed to save screenshot: {}", e),
+                        }
+                    }
+
+                    if let Err(e) = render_manager.render(size) {
+                        eprintln!("Error: {}", e);
+                        event_loop.exit();
+                        return;
                     }
-                    POS = (position.x, position.y);
+                    self.window.as_ref().unwrap().request_redraw();
                 }
             }
-            WindowEvent::MouseInput { device_id: _, state, button:_} => {
-                if state == winit::event::ElementState::Pressed {
-                    unsafe {
-                        PRESSED = true;
-                    }
+            WindowEvent::KeyboardInput {event, ..} if event.state == ElementState::Pressed => {
+                let mut triggers = Vec::new();
+                if let PhysicalKey::Code(code) = event.physical_key {
+                    triggers.push(Trigger::Key(code));
                 }
-                else {
-                    unsafe {
-                        PRESSED = false;
-                    }
+                if let Key::Character(character) = event.key_without_modifiers() {
+                    triggers.push(Trigger::Character(character.to_lowercase()));
                 }
+                self.press(&triggers, event.repeat, event_loop);
             }
-            WindowEvent::KeyboardInput {event, ..} => {
-                if event.state == winit::event::ElementState::Pressed {
-                    match event.key_without_modifiers().as_ref() {
-                        Key::Character("w") => unsafe { MODEL.1 += 0.1; },
-                        Key::Character("s") => unsafe { MODEL.1 -= 0.1; },
-                        Key::Character("a") => unsafe { MODEL.0 -= 0.1; },
-                        Key::Character("d") => unsafe { MODEL.0 += 0.1; },
-                        Key::Named(winit::keyboard::NamedKey::ArrowUp) => unsafe { CAMERA.1 += 0.1; },
-                        Key::Named(winit::keyboard::NamedKey::ArrowDown) => unsafe { CAMERA.1 -= 0.1; },
-                        Key::Named(winit::keyboard::NamedKey::ArrowLeft) => unsafe { CAMERA.0 -= 0.1; },
-                        Key::Named(winit::keyboard::NamedKey::ArrowRight) => unsafe { CAMERA.0 += 0.1; },
-                        Key::Named(winit::keyboard::NamedKey::Escape) => {
-                            event_loop.exit();
-                        },
-                        _ => (),
-                    }
-                }
+            WindowEvent::MouseInput { state: ElementState::Pressed, button, .. } => {
+                self.press(&[Trigger::Mouse(button)], false, event_loop);
             }
             _ => (),
         }
     }
-}
\ No newline at end of file
+
+    // Raw motion keeps arriving while the cursor is locked in place
+    fn device_event(&mut self, _: &ActiveEventLoop, _: DeviceId, event: DeviceEvent) {
+        self.input.handle_device_event(&event);
+    }
+}
+
+// Millisecond timestamps, with a suffix in case the clock didn't move on
+// between two screenshots
+fn screenshot_path() -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0);
+    let mut path = PathBuf::from(format!("screenshot_{}.png", timestamp));
+    let mut suffix = 2;
+    while path.exists() {
+        path = PathBuf::from(format!("screenshot_{}_{}.png", timestamp, suffix));
+        suffix += 1;
+    }
+    path
+}